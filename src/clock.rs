@@ -0,0 +1,133 @@
+//! Timestamps carried in probes.
+
+use std::time::{Instant, SystemTime, UNIX_EPOCH};
+
+/// Wall-clock time in nanoseconds since the UNIX epoch that only ever moves forward.
+///
+/// The wall clock is sampled once at creation and advanced with a monotonic
+/// clock from then on, so RTTs computed from two readings are unaffected by
+/// NTP steps while the values still line up with other hosts' clocks.
+#[derive(Debug, Clone, Copy)]
+pub struct Clock {
+	base_instant: Instant,
+	base_nanos: u64,
+}
+
+impl Clock {
+	pub fn new() -> Self {
+		Clock {
+			base_instant: Instant::now(),
+			base_nanos: wall_nanos(),
+		}
+	}
+
+	pub fn now_nanos(&self) -> u64 {
+		self.base_nanos + self.base_instant.elapsed().as_nanos() as u64
+	}
+
+	/// Convert a wall-clock timestamp, such as one taken by the kernel, to
+	/// this clock, accounting for any drift or steps of the wall clock since
+	/// this clock was created.
+	pub fn adjust_wall(&self, wall: u64) -> u64 {
+		let drift = self.now_nanos() as i64 - wall_nanos() as i64;
+		(wall as i64 + drift) as u64
+	}
+}
+
+/// Delays and clock offset of one probe/reply exchange, NTP style, from the
+/// probe's send time `t1`, the server's receive and transmit times `t2` and
+/// `t3` and the reply's arrival time `t4`. All values are in milliseconds.
+///
+/// The one-way delays include the offset between the two clocks; `offset`
+/// is the server clock minus ours, assuming the path is symmetric, and is
+/// most accurate for exchanges with a small `delay`.
+#[derive(Debug, Clone, Copy)]
+pub struct Exchange {
+	pub forward: f64,
+	pub reverse: f64,
+	/// Round-trip time minus the time the server held the probe.
+	pub delay: f64,
+	pub offset: f64,
+}
+
+impl Exchange {
+	pub fn new(t1: u64, t2: u64, t3: u64, t4: u64) -> Self {
+		let ms = |later: u64, earlier: u64| (later as i64).wrapping_sub(earlier as i64) as f64 / 1_000_000.0;
+		let forward = ms(t2, t1);
+		let reverse = ms(t4, t3);
+		Exchange {
+			forward,
+			reverse,
+			delay: forward + reverse,
+			offset: (forward - reverse) / 2.0,
+		}
+	}
+}
+
+/// Current wall-clock time in nanoseconds since the UNIX epoch.
+pub fn wall_nanos() -> u64 {
+	SystemTime::now()
+		.duration_since(UNIX_EPOCH)
+		.map(|d| d.as_nanos() as u64)
+		.unwrap_or(0)
+}
+
+/// Format nanoseconds since the UNIX epoch as an RFC 3339 UTC timestamp with
+/// millisecond precision, e.g. `2024-05-01T12:34:56.789Z`.
+pub fn format_rfc3339(nanos: u64) -> String {
+	let secs = nanos / 1_000_000_000;
+	let millis = (nanos / 1_000_000) % 1000;
+	let days = (secs / 86_400) as i64;
+	let rem = secs % 86_400;
+
+	// Civil-from-days, see http://howardhinnant.github.io/date_algorithms.html
+	let z = days + 719_468;
+	let era = z.div_euclid(146_097);
+	let doe = z - era * 146_097;
+	let yoe = (doe - doe / 1460 + doe / 36_524 - doe / 146_096) / 365;
+	let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+	let mp = (5 * doy + 2) / 153;
+	let day = doy - (153 * mp + 2) / 5 + 1;
+	let month = if mp < 10 { mp + 3 } else { mp - 9 };
+	let year = yoe + era * 400 + i64::from(month <= 2);
+
+	format!(
+		"{:04}-{:02}-{:02}T{:02}:{:02}:{:02}.{:03}Z",
+		year,
+		month,
+		day,
+		rem / 3600,
+		(rem / 60) % 60,
+		rem % 60,
+		millis
+	)
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn rfc3339_epoch() {
+		assert_eq!(format_rfc3339(0), "1970-01-01T00:00:00.000Z");
+	}
+
+	#[test]
+	fn rfc3339_known_dates() {
+		assert_eq!(format_rfc3339(1_714_566_896_789_000_000), "2024-05-01T12:34:56.789Z");
+		// Leap day in a year divisible by 400
+		assert_eq!(format_rfc3339(951_782_400_123_456_789), "2000-02-29T00:00:00.123Z");
+		assert_eq!(format_rfc3339(1_735_689_599_999_999_999), "2024-12-31T23:59:59.999Z");
+	}
+
+	#[test]
+	fn exchange_offset() {
+		// Server clock 5 ms ahead, 1 ms each way, held for 2 ms
+		let ms = 1_000_000;
+		let e = Exchange::new(0, 6 * ms, 8 * ms, 4 * ms);
+		assert_eq!(e.forward, 6.0);
+		assert_eq!(e.reverse, -4.0);
+		assert_eq!(e.delay, 2.0);
+		assert_eq!(e.offset, 5.0);
+	}
+}
@@ -0,0 +1,53 @@
+//! CSV export of per-probe results (`--csv`).
+
+use crate::clock;
+use crate::responders::ResponderKey;
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::path::Path;
+
+/// One row per probe per responder, flushed as it is written so the file is
+/// usable while a long run is still going.
+pub struct CsvLog {
+	out: BufWriter<File>,
+}
+
+/// A result for one probe and responder.
+pub struct Row<'a> {
+	/// When the reply arrived or the probe was given up on, ns since the UNIX epoch
+	pub time: u64,
+	pub seq: u64,
+	pub key: &'a ResponderKey,
+	/// `on_time`, `late`, `duplicate`, `out_of_order`, `corrupted`, `truncated` or `lost`
+	pub status: &'a str,
+	pub rtt_ms: Option<f64>,
+	/// Hop limit the probe arrived at the responder with
+	pub hop_limit: Option<u8>,
+	/// Size of the probe in bytes
+	pub payload_size: Option<usize>,
+}
+
+impl CsvLog {
+	pub fn create(path: &Path) -> io::Result<Self> {
+		let mut out = BufWriter::new(File::create(path)?);
+		writeln!(out, "timestamp,seq,responder,server_id,status,rtt_ms,hop_limit,payload_size")?;
+		out.flush()?;
+		Ok(CsvLog { out })
+	}
+
+	pub fn write(&mut self, row: &Row) -> io::Result<()> {
+		writeln!(
+			self.out,
+			"{},{},{},{},{},{},{},{}",
+			clock::format_rfc3339(row.time),
+			row.seq,
+			row.key.addr,
+			row.key.server_id.map(|id| format!("{:08x}", id)).unwrap_or_default(),
+			row.status,
+			row.rtt_ms.map(|rtt| format!("{:.3}", rtt)).unwrap_or_default(),
+			row.hop_limit.map(|h| h.to_string()).unwrap_or_default(),
+			row.payload_size.map(|s| s.to_string()).unwrap_or_default(),
+		)?;
+		self.out.flush()
+	}
+}
@@ -0,0 +1,147 @@
+//! Multicast group validation and scope descriptions.
+
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+
+/// clap value parser for `--group`: accepts only multicast addresses.
+pub fn parse_group(value: &str) -> Result<IpAddr, String> {
+	let addr: IpAddr = value
+		.parse()
+		.map_err(|_| format!("'{}' is not an IP address", value))?;
+	if !addr.is_multicast() {
+		let range = if addr.is_ipv4() { "224.0.0.0/4" } else { "ff00::/8" };
+		return Err(format!("{} is not a multicast address (must be in {})", addr, range));
+	}
+	Ok(addr)
+}
+
+pub fn scope_name(addr: &IpAddr) -> &'static str {
+	match addr {
+		IpAddr::V4(addr) => v4_scope_name(addr),
+		IpAddr::V6(addr) => v6_scope_name(addr),
+	}
+}
+
+/// Explain scopes that will likely not behave the way a multicast test expects.
+pub fn scope_warning(addr: &IpAddr) -> Option<&'static str> {
+	match addr {
+		IpAddr::V4(addr) => v4_scope_warning(addr),
+		IpAddr::V6(addr) => v6_scope_warning(addr),
+	}
+}
+
+/// Whether the group is in the source-specific multicast range (RFC 4607):
+/// 232.0.0.0/8 for IPv4, ff3x::/96 for IPv6.
+pub fn is_ssm(addr: &IpAddr) -> bool {
+	match addr {
+		IpAddr::V4(addr) => addr.octets()[0] == 232,
+		IpAddr::V6(addr) => {
+			let segments = addr.segments();
+			segments[0] & 0xfff0 == 0xff30 && segments[1..6].iter().all(|&s| s == 0)
+		}
+	}
+}
+
+/// The 4-bit scope field of an IPv6 multicast address (RFC 4291, RFC 7346).
+fn v6_scope(addr: &Ipv6Addr) -> u8 {
+	addr.octets()[1] & 0x0f
+}
+
+fn v6_scope_name(addr: &Ipv6Addr) -> &'static str {
+	match v6_scope(addr) {
+		0x1 => "interface-local",
+		0x2 => "link-local",
+		0x3 => "realm-local",
+		0x4 => "admin-local",
+		0x5 => "site-local",
+		0x8 => "organization-local",
+		0xe => "global",
+		0x0 | 0xf => "reserved",
+		_ => "unassigned",
+	}
+}
+
+fn v6_scope_warning(addr: &Ipv6Addr) -> Option<&'static str> {
+	match v6_scope(addr) {
+		0x1 => Some("interface-local scope: probes never leave this host"),
+		0x2 => Some("link-local scope: probes will not be forwarded by routers"),
+		0x0 | 0xf => Some("reserved scope: routers and hosts may drop these packets"),
+		0x6 | 0x7 | 0x9..=0xd => Some("unassigned scope: treat as the next smaller assigned scope"),
+		_ => None,
+	}
+}
+
+/// IPv4 has no scope field; scopes come from the address blocks in
+/// RFC 5771 and the administratively scoped ranges in RFC 2365.
+fn v4_scope_name(addr: &Ipv4Addr) -> &'static str {
+	match addr.octets() {
+		[224, 0, 0, _] => "link-local",
+		[232, _, _, _] => "source-specific",
+		[239, 255, _, _] => "site-local",
+		[239, 192..=195, _, _] => "organization-local",
+		[239, _, _, _] => "admin-local",
+		_ => "global",
+	}
+}
+
+fn v4_scope_warning(addr: &Ipv4Addr) -> Option<&'static str> {
+	match addr.octets() {
+		[224, 0, 0, _] => Some("link-local scope (224.0.0.0/24): probes will not be forwarded by routers"),
+		_ => None,
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	fn addr(value: &str) -> IpAddr {
+		value.parse().unwrap()
+	}
+
+	#[test]
+	fn only_multicast_groups() {
+		assert_eq!(parse_group("239.1.2.3"), Ok(addr("239.1.2.3")));
+		assert_eq!(parse_group("ff15::1234"), Ok(addr("ff15::1234")));
+		assert_eq!(parse_group("10.0.0.1"), Err("10.0.0.1 is not a multicast address (must be in 224.0.0.0/4)".into()));
+		assert_eq!(parse_group("2001:db8::1"), Err("2001:db8::1 is not a multicast address (must be in ff00::/8)".into()));
+		assert!(parse_group("not-an-address").is_err());
+	}
+
+	#[test]
+	fn ipv4_scopes() {
+		assert_eq!(scope_name(&addr("224.0.0.251")), "link-local");
+		assert_eq!(scope_name(&addr("232.1.1.1")), "source-specific");
+		// 239.255/16 sits outside 239.192/14, and both inside 239/8
+		assert_eq!(scope_name(&addr("239.255.0.1")), "site-local");
+		assert_eq!(scope_name(&addr("239.192.0.1")), "organization-local");
+		assert_eq!(scope_name(&addr("239.195.255.255")), "organization-local");
+		assert_eq!(scope_name(&addr("239.196.0.1")), "admin-local");
+		assert_eq!(scope_name(&addr("233.252.0.1")), "global");
+		assert!(scope_warning(&addr("224.0.0.1")).is_some());
+		assert_eq!(scope_warning(&addr("239.255.0.1")), None);
+	}
+
+	#[test]
+	fn ipv6_scopes() {
+		assert_eq!(scope_name(&addr("ff01::1")), "interface-local");
+		assert_eq!(scope_name(&addr("ff12::1")), "link-local");
+		assert_eq!(scope_name(&addr("ff15::1234")), "site-local");
+		assert_eq!(scope_name(&addr("ff3e::8000:1")), "global");
+		assert_eq!(scope_name(&addr("ff16::1")), "unassigned");
+		assert_eq!(scope_name(&addr("ff10::1")), "reserved");
+		assert!(scope_warning(&addr("ff02::1")).is_some());
+		assert!(scope_warning(&addr("ff16::1")).is_some());
+		assert_eq!(scope_warning(&addr("ff15::1234")), None);
+	}
+
+	#[test]
+	fn ssm_ranges() {
+		assert!(is_ssm(&addr("232.1.1.1")));
+		assert!(!is_ssm(&addr("239.1.1.1")));
+		assert!(is_ssm(&addr("ff3e::8000:1")));
+		assert!(is_ssm(&addr("ff35::1")));
+		// Unicast-prefix-based (RFC 3306), not source-specific
+		assert!(!is_ssm(&addr("ff3e:30:2001:db8::1")));
+		assert!(!is_ssm(&addr("ff1e::8000:1")));
+	}
+}
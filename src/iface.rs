@@ -0,0 +1,165 @@
+//! Network interface lookup.
+
+use std::io::{self, ErrorKind};
+use std::net::{IpAddr, Ipv4Addr};
+
+pub fn get_interface_index(if_name: &str) -> io::Result<u32> {
+	#[cfg(unix)]
+	{
+		use std::ffi::CString;
+		let c_name = CString::new(if_name)
+			.map_err(|_| io::Error::new(ErrorKind::InvalidInput, "Invalid interface name"))?;
+
+		let index = unsafe { libc::if_nametoindex(c_name.as_ptr()) };
+		if index == 0 {
+			return Err(io::Error::new(
+				ErrorKind::NotFound,
+				format!("Interface '{}' not found", if_name),
+			));
+		}
+		Ok(index)
+	}
+
+	#[cfg(windows)]
+	{
+		// On Windows, try to get the interface index
+		// This is a simplified approach - on Windows you may need to use
+		// the Windows API (GetAdaptersAddresses) for better interface lookup
+
+		// Try to parse as a number first (Windows sometimes uses indices directly)
+		if let Ok(index) = if_name.parse::<u32>() {
+			return Ok(index);
+		}
+
+		// Otherwise, return error with helpful message
+		Err(io::Error::new(
+			ErrorKind::NotFound,
+			format!(
+				"Interface '{}' lookup not fully supported on Windows. \
+				Try using the interface index number instead (find with 'netsh interface ipv6 show interface')",
+				if_name
+			),
+		))
+	}
+}
+
+/// First IPv4 address assigned to the named interface.
+pub fn get_interface_ipv4(if_name: &str) -> io::Result<Ipv4Addr> {
+	#[cfg(unix)]
+	{
+		use std::ffi::CStr;
+		let mut addrs: *mut libc::ifaddrs = std::ptr::null_mut();
+		if unsafe { libc::getifaddrs(&mut addrs) } != 0 {
+			return Err(io::Error::last_os_error());
+		}
+
+		let mut found = None;
+		let mut cur = addrs;
+		while !cur.is_null() {
+			let ifa = unsafe { &*cur };
+			cur = ifa.ifa_next;
+			if ifa.ifa_addr.is_null() || i32::from(unsafe { (*ifa.ifa_addr).sa_family }) != libc::AF_INET {
+				continue;
+			}
+			let name = unsafe { CStr::from_ptr(ifa.ifa_name) };
+			if name.to_bytes() == if_name.as_bytes() {
+				let sin = unsafe { &*(ifa.ifa_addr as *const libc::sockaddr_in) };
+				found = Some(Ipv4Addr::from(u32::from_be(sin.sin_addr.s_addr)));
+				break;
+			}
+		}
+		unsafe { libc::freeifaddrs(addrs) };
+
+		found.ok_or_else(|| {
+			io::Error::new(
+				ErrorKind::NotFound,
+				format!("Interface '{}' not found or has no IPv4 address", if_name),
+			)
+		})
+	}
+
+	#[cfg(windows)]
+	{
+		// Accept the interface's IPv4 address directly
+		if_name.parse::<Ipv4Addr>().map_err(|_| {
+			io::Error::new(
+				ErrorKind::NotFound,
+				format!(
+					"Interface '{}' lookup not supported on Windows for IPv4. \
+					Use the interface's IPv4 address instead (find with 'ipconfig')",
+					if_name
+				),
+			)
+		})
+	}
+}
+
+/// An interface a server can join the group on.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Interface {
+	pub name: String,
+	pub index: u32,
+}
+
+/// Interfaces that are up, multicast-capable and have an address of the same
+/// family as `family`, sorted by index.
+pub fn list_multicast_interfaces(family: &IpAddr) -> io::Result<Vec<Interface>> {
+	#[cfg(unix)]
+	{
+		use std::ffi::CStr;
+		let want = if family.is_ipv4() { libc::AF_INET } else { libc::AF_INET6 };
+
+		let mut addrs: *mut libc::ifaddrs = std::ptr::null_mut();
+		if unsafe { libc::getifaddrs(&mut addrs) } != 0 {
+			return Err(io::Error::last_os_error());
+		}
+
+		let mut interfaces: Vec<Interface> = Vec::new();
+		let mut cur = addrs;
+		while !cur.is_null() {
+			let ifa = unsafe { &*cur };
+			cur = ifa.ifa_next;
+			let flags = ifa.ifa_flags as libc::c_int;
+			if flags & libc::IFF_UP == 0 || flags & libc::IFF_MULTICAST == 0 {
+				continue;
+			}
+			if ifa.ifa_addr.is_null() || i32::from(unsafe { (*ifa.ifa_addr).sa_family }) != want {
+				continue;
+			}
+			let index = unsafe { libc::if_nametoindex(ifa.ifa_name) };
+			if index == 0 || interfaces.iter().any(|i| i.index == index) {
+				continue;
+			}
+			let name = unsafe { CStr::from_ptr(ifa.ifa_name) }.to_string_lossy().into_owned();
+			interfaces.push(Interface { name, index });
+		}
+		unsafe { libc::freeifaddrs(addrs) };
+
+		interfaces.sort_by_key(|i| i.index);
+		Ok(interfaces)
+	}
+
+	#[cfg(windows)]
+	{
+		let _ = family;
+		Err(io::Error::new(
+			ErrorKind::Unsupported,
+			"Interface enumeration is not supported on Windows; pass each interface with --interface",
+		))
+	}
+}
+
+/// Name of the interface with the given index, or the index itself if it
+/// can't be looked up.
+pub fn interface_name(index: u32) -> String {
+	#[cfg(unix)]
+	{
+		let mut buf = [0 as libc::c_char; libc::IF_NAMESIZE];
+		let ret = unsafe { libc::if_indextoname(index, buf.as_mut_ptr()) };
+		if !ret.is_null() {
+			let name = unsafe { std::ffi::CStr::from_ptr(buf.as_ptr()) };
+			return name.to_string_lossy().into_owned();
+		}
+	}
+	format!("if#{}", index)
+}
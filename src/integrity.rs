@@ -0,0 +1,135 @@
+//! Checking that probe padding survives the round trip.
+//!
+//! Probes are padded with a pattern derived from their sequence number (see
+//! `packet::padding_byte`). The server either echoes the padding back or
+//! sends its length and CRC-32, and the client checks either against the
+//! pattern it sent.
+
+use crate::packet::{self, Packet};
+use clap::ValueEnum;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum EchoMode {
+	/// Reply with the length and CRC-32 of the padding
+	Checksum,
+	/// Reply with the padding itself, making replies as large as probes
+	Payload,
+}
+
+/// Outcome of checking a reply's payload.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Verdict {
+	/// The padding arrived as sent
+	Intact,
+	/// The padding doesn't match the pattern it was sent with
+	Corrupted,
+	/// The probe or the reply was cut short by a receive buffer
+	Truncated,
+	/// The probe carried no padding, or the server didn't echo it
+	Unchecked,
+}
+
+impl Verdict {
+	/// Name in JSON output.
+	pub fn name(&self) -> &'static str {
+		match self {
+			Verdict::Intact => "intact",
+			Verdict::Corrupted => "corrupted",
+			Verdict::Truncated => "truncated",
+			Verdict::Unchecked => "unchecked",
+		}
+	}
+}
+
+/// CRC-32 (IEEE 802.3), as used by zlib and Ethernet.
+pub fn crc32(data: &[u8]) -> u32 {
+	let mut crc = !0u32;
+	for &byte in data {
+		crc ^= u32::from(byte);
+		for _ in 0..8 {
+			crc = if crc & 1 != 0 { (crc >> 1) ^ 0xedb8_8320 } else { crc >> 1 };
+		}
+	}
+	!crc
+}
+
+/// Whether `padding` follows the pattern of probe `seq`.
+pub fn padding_intact(seq: u64, padding: &[u8]) -> bool {
+	padding.iter().enumerate().all(|(i, &byte)| byte == packet::padding_byte(seq, i))
+}
+
+/// Add the server's echo of the probe's padding to `reply`, if it had any.
+pub fn echo(ping: &Packet, reply: &mut Packet, mode: EchoMode) {
+	let Some(padding) = ping.tlv(packet::TLV_PADDING) else {
+		return;
+	};
+	match mode {
+		EchoMode::Checksum => {
+			let mut value = (padding.len() as u32).to_be_bytes().to_vec();
+			value.extend_from_slice(&crc32(padding).to_be_bytes());
+			reply.push_tlv(packet::TLV_PAYLOAD_CHECKSUM, value);
+		}
+		EchoMode::Payload => reply.push_tlv(packet::TLV_PADDING, padding.to_vec()),
+	}
+}
+
+/// Check the echoed payload of a reply. `reply_truncated` is set when the
+/// reply itself didn't fit the client's receive buffer.
+pub fn verify(pong: &Packet, reply_truncated: bool) -> Verdict {
+	if reply_truncated || pong.flags & packet::FLAG_TRUNCATED != 0 {
+		return Verdict::Truncated;
+	}
+	if let Some(padding) = pong.tlv(packet::TLV_PADDING) {
+		return if padding_intact(pong.seq, padding) { Verdict::Intact } else { Verdict::Corrupted };
+	}
+	match pong.tlv(packet::TLV_PAYLOAD_CHECKSUM) {
+		Some(&[l0, l1, l2, l3, c0, c1, c2, c3]) => {
+			let len = u32::from_be_bytes([l0, l1, l2, l3]) as usize;
+			let expected: Vec<u8> = (0..len).map(|i| packet::padding_byte(pong.seq, i)).collect();
+			if crc32(&expected) == u32::from_be_bytes([c0, c1, c2, c3]) {
+				Verdict::Intact
+			} else {
+				Verdict::Corrupted
+			}
+		}
+		Some(_) => Verdict::Corrupted,
+		None => Verdict::Unchecked,
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::packet::MessageType;
+
+	#[test]
+	fn crc32_known_vectors() {
+		assert_eq!(crc32(b""), 0);
+		assert_eq!(crc32(b"a"), 0xe8b7_be43);
+		assert_eq!(crc32(b"123456789"), 0xcbf4_3926);
+		assert_eq!(crc32(b"The quick brown fox jumps over the lazy dog"), 0x414f_a339);
+	}
+
+	fn exchange(mode: EchoMode, corrupt: bool) -> Verdict {
+		let mut ping = Packet::new(MessageType::Ping, 1, 7, 0);
+		ping.pad_to(128);
+		let mut encoded = ping.encode();
+		if corrupt {
+			*encoded.last_mut().unwrap() ^= 0xff;
+		}
+		let ping = Packet::decode(&encoded).unwrap();
+		let mut pong = Packet::reply_to(&ping);
+		echo(&ping, &mut pong, mode);
+		verify(&Packet::decode(&pong.encode()).unwrap(), false)
+	}
+
+	#[test]
+	fn verdicts() {
+		assert_eq!(exchange(EchoMode::Checksum, false), Verdict::Intact);
+		assert_eq!(exchange(EchoMode::Checksum, true), Verdict::Corrupted);
+		assert_eq!(exchange(EchoMode::Payload, false), Verdict::Intact);
+		assert_eq!(exchange(EchoMode::Payload, true), Verdict::Corrupted);
+		assert_eq!(verify(&Packet::new(MessageType::Pong, 1, 7, 0), false), Verdict::Unchecked);
+		assert_eq!(verify(&Packet::new(MessageType::Pong, 1, 7, 0), true), Verdict::Truncated);
+	}
+}
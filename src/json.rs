@@ -0,0 +1,257 @@
+//! JSON Lines output (`--format json`).
+//!
+//! Every event is written to stdout as one JSON object per line, with an
+//! `event` field naming it. Human-oriented status messages go to stderr in
+//! this mode so stdout stays machine-readable. Errors go to stderr, or are
+//! held for the dashboard's status line while `--tui` owns the terminal.
+
+use clap::ValueEnum;
+use std::fmt::Write;
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum Format {
+	/// Human-readable text
+	Text,
+	/// One JSON object per line
+	Json,
+}
+
+static JSON: AtomicBool = AtomicBool::new(false);
+
+pub fn set_format(format: Format) {
+	JSON.store(format == Format::Json, Ordering::Relaxed);
+}
+
+pub fn enabled() -> bool {
+	JSON.load(Ordering::Relaxed)
+}
+
+/// Print a status message: to stdout in text mode, to stderr in JSON mode.
+macro_rules! info {
+	($($arg:tt)*) => {
+		if $crate::json::enabled() {
+			eprintln!($($arg)*);
+		} else {
+			println!($($arg)*);
+		}
+	};
+}
+pub(crate) use info;
+
+static CAPTURE: AtomicBool = AtomicBool::new(false);
+/// The latest message reported while reports are captured.
+static LAST_REPORT: Mutex<Option<String>> = Mutex::new(None);
+
+/// Hold reported errors for `last_report` instead of printing them, while
+/// something else owns the terminal.
+pub fn capture_reports(enabled: bool) {
+	CAPTURE.store(enabled, Ordering::Relaxed);
+}
+
+pub fn capturing_reports() -> bool {
+	CAPTURE.load(Ordering::Relaxed)
+}
+
+pub fn set_last_report(message: String) {
+	*LAST_REPORT.lock().unwrap() = Some(message);
+}
+
+pub fn last_report() -> Option<String> {
+	LAST_REPORT.lock().unwrap().clone()
+}
+
+/// Report an error: to stderr, or to `last_report` while reports are captured.
+macro_rules! report {
+	($($arg:tt)*) => {
+		if $crate::json::capturing_reports() {
+			$crate::json::set_last_report(format!($($arg)*));
+		} else {
+			eprintln!($($arg)*);
+		}
+	};
+}
+pub(crate) use report;
+
+/// A value that can be written as JSON.
+pub trait Value {
+	fn write_json(&self, out: &mut String);
+}
+
+impl Value for str {
+	fn write_json(&self, out: &mut String) {
+		out.push('"');
+		for c in self.chars() {
+			match c {
+				'"' => out.push_str("\\\""),
+				'\\' => out.push_str("\\\\"),
+				'\n' => out.push_str("\\n"),
+				'\r' => out.push_str("\\r"),
+				'\t' => out.push_str("\\t"),
+				c if (c as u32) < 0x20 => {
+					let _ = write!(out, "\\u{:04x}", c as u32);
+				}
+				c => out.push(c),
+			}
+		}
+		out.push('"');
+	}
+}
+
+impl Value for String {
+	fn write_json(&self, out: &mut String) {
+		self.as_str().write_json(out);
+	}
+}
+
+macro_rules! integer_value {
+	($($t:ty),*) => {
+		$(impl Value for $t {
+			fn write_json(&self, out: &mut String) {
+				let _ = write!(out, "{}", self);
+			}
+		})*
+	};
+}
+integer_value!(u8, u16, u32, u64, usize, i64);
+
+impl Value for f64 {
+	fn write_json(&self, out: &mut String) {
+		if self.is_finite() {
+			// Microsecond resolution is plenty for milliseconds and percentages
+			let _ = write!(out, "{}", (self * 1000.0).round() / 1000.0);
+		} else {
+			out.push_str("null");
+		}
+	}
+}
+
+impl Value for bool {
+	fn write_json(&self, out: &mut String) {
+		out.push_str(if *self { "true" } else { "false" });
+	}
+}
+
+impl<T: Value> Value for Option<T> {
+	fn write_json(&self, out: &mut String) {
+		match self {
+			Some(value) => value.write_json(out),
+			None => out.push_str("null"),
+		}
+	}
+}
+
+impl<T: Value> Value for Vec<T> {
+	fn write_json(&self, out: &mut String) {
+		out.push('[');
+		for (i, value) in self.iter().enumerate() {
+			if i > 0 {
+				out.push(',');
+			}
+			value.write_json(out);
+		}
+		out.push(']');
+	}
+}
+
+impl<T: Value + ?Sized> Value for &T {
+	fn write_json(&self, out: &mut String) {
+		(**self).write_json(out);
+	}
+}
+
+/// A JSON object built field by field, in insertion order.
+#[derive(Debug, Clone)]
+pub struct Object(String);
+
+impl Object {
+	/// An empty object.
+	pub fn new() -> Self {
+		Object("{".to_string())
+	}
+
+	/// An object for an event, starting with its `event` and `time` fields.
+	pub fn event(name: &str, time: u64) -> Self {
+		Object::new()
+			.field("event", name)
+			.field("time", crate::clock::format_rfc3339(time))
+	}
+
+	pub fn field<T: Value>(mut self, key: &str, value: T) -> Self {
+		if self.0.len() > 1 {
+			self.0.push(',');
+		}
+		key.write_json(&mut self.0);
+		self.0.push(':');
+		value.write_json(&mut self.0);
+		self
+	}
+
+	/// Write the object to stdout as one line.
+	pub fn emit(self) {
+		let mut line = self.0;
+		line.push('}');
+		println!("{}", line);
+	}
+}
+
+impl Value for Object {
+	fn write_json(&self, out: &mut String) {
+		out.push_str(&self.0);
+		out.push('}');
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	fn render<T: Value>(value: T) -> String {
+		let mut out = String::new();
+		value.write_json(&mut out);
+		out
+	}
+
+	#[test]
+	fn strings_are_escaped() {
+		assert_eq!(render("plain"), r#""plain""#);
+		assert_eq!(render("say \"hi\"\\"), r#""say \"hi\"\\""#);
+		assert_eq!(render("a\nb\tc\r"), r#""a\nb\tc\r""#);
+		assert_eq!(render("\u{1}\u{1f}"), r#""\u0001\u001f""#);
+		assert_eq!(render("→ ✓"), "\"→ ✓\"");
+	}
+
+	#[test]
+	fn numbers() {
+		assert_eq!(render(1.234_56f64), "1.235");
+		assert_eq!(render(2.0f64), "2");
+		assert_eq!(render(-0.5f64), "-0.5");
+		assert_eq!(render(f64::NAN), "null");
+		assert_eq!(render(f64::INFINITY), "null");
+		assert_eq!(render(u64::MAX), "18446744073709551615");
+	}
+
+	#[test]
+	fn objects() {
+		let inner = vec![Object::new().field("seq", 1u64), Object::new().field("seq", 2u64)];
+		let object = Object::new()
+			.field("name", "eth0")
+			.field("server_id", None::<String>)
+			.field("rtt_ms", f64::NAN)
+			.field("lost", false)
+			.field("per_responder", inner)
+			.field("empty", Vec::<Object>::new());
+		assert_eq!(
+			render(object),
+			r#"{"name":"eth0","server_id":null,"rtt_ms":null,"lost":false,"per_responder":[{"seq":1},{"seq":2}],"empty":[]}"#
+		);
+		assert_eq!(render(Object::new()), "{}");
+	}
+
+	#[test]
+	fn events_start_with_name_and_time() {
+		let object = Object::event("probe", 1_500_000_000).field("seq", 7u64);
+		assert_eq!(render(object), r#"{"event":"probe","time":"1970-01-01T00:00:01.500Z","seq":7}"#);
+	}
+}
@@ -1,51 +1,222 @@
+mod clock;
+mod csv;
+mod group;
+mod iface;
+mod integrity;
+mod json;
+mod metrics;
+mod packet;
+mod pktinfo;
+mod pmtu;
+mod push;
+mod qos;
+mod responders;
+mod ssm;
+mod stats;
+mod tui;
+
 use clap::Parser;
-use socket2::{Domain, Protocol, Socket, Type};
+use clock::{Clock, Exchange};
+use csv::CsvLog;
+use iface::Interface;
+use metrics::InterfaceCounters;
+use integrity::{EchoMode, Verdict};
+use json::{info, report, Format, Object};
+use packet::{MessageType, Packet};
+use push::{Endpoint, PushFormat, Pusher};
+use responders::{ReplyClass, ResponderEvent, ResponderKey, ResponderTable};
+use ssm::FilterMode;
+use socket2::{Domain, InterfaceIndexOrAddress, Protocol, SockRef, Socket, Type};
+use std::collections::BTreeMap;
 use std::io::{self, ErrorKind};
-use std::net::{Ipv6Addr, SocketAddr, SocketAddrV6};
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr, UdpSocket};
+use std::path::PathBuf;
+use std::process::ExitCode;
+use std::sync::mpsc::{self, RecvTimeoutError};
 use std::sync::atomic::{AtomicU64, Ordering};
-use std::sync::Arc;
+use std::sync::{Arc, Mutex};
 use std::time::{Duration, Instant};
 
-const MULTICAST_ADDR: Ipv6Addr = Ipv6Addr::new(0xff12, 0xc09, 0x3199, 0xe8ba, 0x6f6f, 0x7d23, 0xe6ae, 0xd85d);
-const PORT: u16 = 9999;
-const BUFFER_SIZE: usize = 1024;
+const DEFAULT_GROUP: IpAddr = IpAddr::V6(Ipv6Addr::new(0xff12, 0xc09, 0x3199, 0xe8ba, 0x6f6f, 0x7d23, 0xe6ae, 0xd85d));
+const DEFAULT_PORT: u16 = 9999;
+const DEFAULT_BUFFER_SIZE: usize = 1024;
 
 #[derive(Parser, Debug)]
-#[command(author, version, about = "IPv6 Multicast Client/Server Monitor", long_about = None)]
+#[command(author, version, about = "IPv4/IPv6 Multicast Client/Server Monitor", long_about = None)]
 struct Args {
 	/// Run in server mode (listen for multicast and respond with unicast)
 	#[arg(short, long)]
 	server: bool,
 
+	/// Output format; json writes one object per event to stdout
+	#[arg(long, value_enum, default_value_t = Format::Text)]
+	format: Format,
+
+	/// Show a live dashboard of responders instead of scrolling output (client mode)
+	#[arg(long, conflicts_with = "format")]
+	tui: bool,
+
 	/// Interval in milliseconds between multicast requests (client mode)
 	#[arg(short = 'n', long, default_value = "1000")]
 	interval: u64,
 
-	/// Network interface name (e.g., eth0, wlan0, Ethernet)
+	/// Network interface name (e.g., eth0, wlan0, Ethernet).
+	/// The server accepts it several times to join the group on each interface
 	#[arg(short = 'i', long)]
-	interface: Option<String>,
+	interface: Vec<String>,
+
+	/// Join the group on every up, multicast-capable interface (server mode)
+	#[arg(long, conflicts_with = "interface")]
+	all_interfaces: bool,
+
+	/// Multicast group to send probes to / listen on; IPv4 or IPv6 is picked from the address
+	#[arg(short = 'g', long, default_value_t = DEFAULT_GROUP, value_parser = group::parse_group)]
+	group: IpAddr,
+
+	/// Source address for a source-specific join (repeatable, server mode)
+	#[arg(long = "source", value_name = "ADDR")]
+	sources: Vec<IpAddr>,
+
+	/// Whether --source addresses are the only senders to receive from or the ones to block
+	#[arg(long, value_enum, default_value_t = FilterMode::Include, requires = "sources")]
+	filter_mode: FilterMode,
+
+	/// UDP port of the multicast group
+	#[arg(short = 'p', long, default_value_t = DEFAULT_PORT)]
+	port: u16,
+
+	/// Size of the receive buffer in bytes; longer datagrams are truncated
+	#[arg(long, default_value_t = DEFAULT_BUFFER_SIZE, value_parser = parse_buffer_size)]
+	buffer_size: usize,
+
+	/// Hop limit (TTL for IPv4) of multicast probes (client mode, default 1)
+	#[arg(long, value_parser = clap::value_parser!(u8).range(1..))]
+	hops: Option<u8>,
+
+	/// Loop probes back to servers on this host (client mode, the default)
+	#[arg(long, overrides_with = "no_loopback")]
+	loopback: bool,
+
+	/// Don't loop probes back to servers on this host (client mode)
+	#[arg(long, overrides_with = "loopback")]
+	no_loopback: bool,
+
+	/// Step the probe hop limit through 1..=MAX to find how far away each responder is.
+	/// Without --count or --deadline the client stops after one sweep (client mode)
+	#[arg(long, value_name = "MAX", value_parser = clap::value_parser!(u8).range(1..), conflicts_with = "hops")]
+	ttl_sweep: Option<u8>,
+
+	/// Pad probes to this many bytes with a pattern the server can check (client mode)
+	#[arg(long, value_name = "BYTES", value_parser = parse_probe_size)]
+	size: Option<usize>,
+
+	/// Step the probe size up to MAX bytes with don't-fragment set, to find the largest
+	/// probe each responder receives. Without --count or --deadline the client stops
+	/// after one sweep (client mode)
+	#[arg(long, value_name = "MAX", value_parser = parse_probe_size, conflicts_with_all = ["size", "ttl_sweep"])]
+	pmtu_sweep: Option<usize>,
+
+	/// Size increment of --pmtu-sweep in bytes
+	#[arg(long, value_name = "BYTES", default_value = "64", value_parser = clap::value_parser!(u64).range(1..))]
+	size_step: u64,
+
+	/// How the server returns probe padding for the client to verify (server mode)
+	#[arg(long, value_enum, default_value_t = EchoMode::Checksum)]
+	echo: EchoMode,
+
+	/// DSCP to mark probes (client mode) or replies (server mode) with, 0-63
+	#[arg(long, value_parser = clap::value_parser!(u8).range(0..64))]
+	dscp: Option<u8>,
+
+	/// Report a responder as lost after this many consecutive unanswered probes (client mode)
+	#[arg(long, default_value = "3", value_parser = clap::value_parser!(u64).range(1..))]
+	lost_after: u64,
+
+	/// Timestamp received packets in the kernel (SO_TIMESTAMPNS) rather than after
+	/// they reach userspace, falling back to userspace timing where unsupported.
+	/// Send times are still taken in userspace
+	#[arg(long)]
+	kernel_timestamps: bool,
+
+	/// Serve Prometheus metrics at http://ADDR/metrics, e.g. 0.0.0.0:9100
+	#[arg(long, value_name = "ADDR")]
+	metrics_listen: Option<SocketAddr>,
+
+	/// Push statistics to tcp://HOST:PORT or udp://HOST:PORT every --push-interval (client mode)
+	#[arg(long, value_name = "URL", value_parser = push::parse_endpoint)]
+	push: Option<Endpoint>,
+
+	/// Protocol to push statistics with
+	#[arg(long, value_enum, default_value_t = PushFormat::Influx, requires = "push")]
+	push_format: PushFormat,
+
+	/// How often to push statistics, e.g. 10s or 1m
+	#[arg(long, value_name = "DURATION", default_value = "10s", value_parser = parse_duration)]
+	push_interval: Duration,
+
+	/// Write one row per probe and responder to this CSV file (client mode)
+	#[arg(long, value_name = "FILE")]
+	csv: Option<PathBuf>,
+
+	/// Count replies arriving later than this after their probe as late, e.g. 250ms;
+	/// defaults to the interval, i.e. after the next probe was sent (client mode)
+	#[arg(long, value_name = "DURATION", value_parser = parse_duration)]
+	late_after: Option<Duration>,
+
+	/// Stop after sending this many probes (client mode)
+	#[arg(short = 'c', long, value_parser = clap::value_parser!(u64).range(1..))]
+	count: Option<u64>,
+
+	/// Stop after this long, e.g. 30, 90s, 15m, 2h; plain numbers are seconds (client mode)
+	#[arg(short = 'w', long, value_parser = parse_duration)]
+	deadline: Option<Duration>,
+
+	/// Also exit non-zero if any responder's loss exceeds this percentage (client mode).
+	/// The client always fails when no replies were received at all
+	#[arg(long, value_name = "PERCENT", value_parser = parse_loss_percent)]
+	max_loss: Option<f64>,
 }
 
-fn main() -> io::Result<()> {
+impl Args {
+	fn group_addr(&self) -> SocketAddr {
+		SocketAddr::new(self.group, self.port)
+	}
+
+	/// The wildcard address of the group's address family.
+	fn unspecified(&self) -> IpAddr {
+		match self.group {
+			IpAddr::V4(_) => IpAddr::V4(Ipv4Addr::UNSPECIFIED),
+			IpAddr::V6(_) => IpAddr::V6(Ipv6Addr::UNSPECIFIED),
+		}
+	}
+}
+
+fn main() -> io::Result<ExitCode> {
 	let args = Args::parse();
+	json::set_format(args.format);
+
+	info!("Multicast group {} has {} scope", args.group, group::scope_name(&args.group));
+	if let Some(warning) = group::scope_warning(&args.group) {
+		eprintln!("Warning: {}", warning);
+	}
 
 	if args.server {
-		println!("Starting server mode...");
-		println!("Listening on multicast address: [{}]:{}", MULTICAST_ADDR, PORT);
-		run_server(args.interface)?;
+		info!("Starting server mode...");
+		info!("Listening on multicast address: {}", args.group_addr());
+		run_server(&args)?;
+		Ok(ExitCode::SUCCESS)
 	} else {
-		println!("Starting client mode...");
-		println!("Sending multicast requests every {} ms", args.interval);
-		println!("Target: [{}]:{}", MULTICAST_ADDR, PORT);
-		run_client(args.interval, args.interface)?;
+		info!("Starting client mode...");
+		info!("Sending multicast requests every {} ms", args.interval);
+		info!("Target: {}", args.group_addr());
+		let ok = run_client(&args)?;
+		Ok(if ok { ExitCode::SUCCESS } else { ExitCode::FAILURE })
 	}
-
-	Ok(())
 }
 
-fn run_server(interface: Option<String>) -> io::Result<()> {
+fn run_server(args: &Args) -> io::Result<()> {
 	// Create UDP socket
-	let socket = Socket::new(Domain::IPV6, Type::DGRAM, Some(Protocol::UDP))?;
+	let socket = Socket::new(Domain::for_address(args.group_addr()), Type::DGRAM, Some(Protocol::UDP))?;
 
 	// Allow address reuse
 	socket.set_reuse_address(true)?;
@@ -53,40 +224,172 @@ fn run_server(interface: Option<String>) -> io::Result<()> {
 	socket.set_reuse_port(true)?;
 
 	// Bind to the multicast port
-	let bind_addr = SocketAddrV6::new(Ipv6Addr::UNSPECIFIED, PORT, 0, 0);
+	let bind_addr = SocketAddr::new(args.unspecified(), args.port);
 	socket.bind(&bind_addr.into())?;
 
-	// Join the multicast group
-	let interface_index = if let Some(ref if_name) = interface {
-		get_interface_index(if_name)?
+	// Join the multicast group on each requested interface
+	let interfaces = if args.all_interfaces {
+		let interfaces = iface::list_multicast_interfaces(&args.group)?;
+		if interfaces.is_empty() {
+			return Err(io::Error::new(ErrorKind::NotFound, "No multicast-capable interfaces found"));
+		}
+		interfaces
+	} else if args.interface.is_empty() {
+		vec![Interface { name: "default".to_string(), index: 0 }]
 	} else {
-		0 // Default interface
+		args.interface
+			.iter()
+			.map(|name| Ok(Interface { name: name.clone(), index: iface::get_interface_index(name)? }))
+			.collect::<io::Result<Vec<_>>>()?
 	};
 
-	socket.join_multicast_v6(&MULTICAST_ADDR, interface_index)?;
-	println!("Joined multicast group on interface index {}", interface_index);
+	if args.sources.is_empty() && group::is_ssm(&args.group) {
+		eprintln!("Warning: {} is a source-specific group; use --source to join it", args.group);
+	}
+	for interface in &interfaces {
+		if args.sources.is_empty() {
+			match args.group {
+				IpAddr::V4(group) => {
+					socket.join_multicast_v4_n(&group, &InterfaceIndexOrAddress::Index(interface.index))?
+				}
+				IpAddr::V6(group) => socket.join_multicast_v6(&group, interface.index)?,
+			}
+			info!("Joined multicast group on {} (index {})", interface.name, interface.index);
+		} else {
+			ssm::join_filtered(&socket, args.group, &args.sources, args.filter_mode, interface.index)?;
+			let sources: Vec<String> = args.sources.iter().map(|s| s.to_string()).collect();
+			info!("Joined multicast group on {} (index {}) ({:?} sources: {})",
+					 interface.name, interface.index, args.filter_mode, sources.join(", "));
+		}
+	}
+
+	let server_id = random_id();
+	info!("Server id: {:08x}", server_id);
 
-	let mut buf = [0u8; BUFFER_SIZE];
+	// Replies are unicast, so they leave with the unicast hop limit
+	let reply_hop_limit = match args.group {
+		IpAddr::V4(_) => socket.ttl()?,
+		IpAddr::V6(_) => socket.unicast_hops_v6()?,
+	} as u8;
+
+	if let Some(dscp) = args.dscp {
+		qos::set_dscp(&socket, args.group, dscp)?;
+		info!("Marking replies with DSCP {}", qos::dscp_name(dscp));
+	}
+	let reply_tclass = args.dscp.unwrap_or(0) << 2;
+
+	let socket: UdpSocket = socket.into();
+	pktinfo::enable(&socket, args.group.is_ipv4())?;
+	enable_kernel_timestamps(&socket, args.kernel_timestamps);
+	let clock = Clock::new();
+	let mut buf = vec![0u8; args.buffer_size];
 	let mut packet_count = 0u64;
 
+	let counters: Arc<Mutex<BTreeMap<String, InterfaceCounters>>> = Arc::default();
+	if let Some(addr) = args.metrics_listen {
+		let counters = Arc::clone(&counters);
+		metrics::serve(addr, move || {
+			let mut w = metrics::Writer::default();
+			metrics::write_server_metrics(&mut w, &counters.lock().unwrap());
+			w.finish()
+		})?;
+		info!("Serving metrics at http://{}/metrics", addr);
+	}
+
 	loop {
-		match socket.recv_from(&mut buf) {
-			Ok((len, addr)) => {
+		match pktinfo::recv(&socket, &mut buf) {
+			Ok(info) => {
+				let received_at = match info.timestamp {
+					Some(timestamp) => clock.adjust_wall(timestamp),
+					None => clock.now_nanos(),
+				};
 				packet_count += 1;
-				let client_addr = match addr.as_socket() {
-					Some(socket_addr) => socket_addr,
-					None => {
-						eprintln!("Invalid address format");
+				let len = info.len;
+				let client_addr = info.source;
+
+				let decoded = match info.truncated_from {
+					Some(_) => Packet::decode_truncated(&buf[..len]),
+					None => Packet::decode(&buf[..len]),
+				};
+				let ping = match decoded {
+					Ok(p) if p.msg_type == MessageType::Ping => p,
+					Ok(p) => {
+						eprintln!("[{}] Ignoring {:?} from {}", packet_count, p.msg_type, client_addr);
+						continue;
+					}
+					Err(e) => {
+						eprintln!("[{}] Invalid packet from {}: {}", packet_count, client_addr, e);
 						continue;
 					}
 				};
 
-				println!("[{}] Received {} bytes from {}", packet_count, len, client_addr);
+				let dscp = qos::describe(ping.tlv_u8(packet::TLV_TCLASS), info.tclass)
+					.map(|d| format!(" dscp {}", d))
+					.unwrap_or_default();
+				let size = info.truncated_from.unwrap_or(len);
+				let interface = info.if_index.map(iface::interface_name).unwrap_or_else(|| "unknown".to_string());
+				counters.lock().unwrap().entry(interface.clone()).or_default().requests += 1;
+				let corrupted = ping.tlv(packet::TLV_PADDING).is_some_and(|padding| !integrity::padding_intact(ping.seq, padding));
+				if !json::enabled() {
+					println!("[{}] Received {} bytes from {} {} (session={:08x} seq={} {}{})",
+							 packet_count, size, client_addr, describe_arrival(&info), ping.session_id, ping.seq,
+							 describe_hops(ping.tlv_u8(packet::TLV_HOP_LIMIT), info.hop_limit), dscp);
+				}
+				if corrupted {
+					eprintln!("[{}] Warning: padding of probe seq={} is corrupted", packet_count, ping.seq);
+				}
+				if info.truncated_from.is_some() {
+					eprintln!("[{}] Warning: only the first {} of {} bytes fit the receive buffer; raise --buffer-size",
+							  packet_count, len, size);
+				}
 
-				// Send unicast response back to the client
-				let response = format!("RESPONSE:{}", packet_count);
-				match socket.send_to(response.as_bytes(), &addr) {
-					Ok(_) => println!("[{}] Sent response to {}", packet_count, client_addr),
+				// Send unicast response back to the client, out of the interface the
+				// request came in on
+				let mut response = Packet::reply_to(&ping);
+				response.push_tlv(packet::TLV_SERVER_ID, server_id.to_be_bytes().to_vec());
+				response.push_tlv(packet::TLV_HOP_LIMIT, vec![reply_hop_limit]);
+				if let Some(hop_limit) = info.hop_limit {
+					response.push_tlv(packet::TLV_RECEIVED_HOP_LIMIT, vec![hop_limit]);
+				}
+				response.push_tlv(packet::TLV_TCLASS, vec![reply_tclass]);
+				if let Some(tclass) = info.tclass {
+					response.push_tlv(packet::TLV_RECEIVED_TCLASS, vec![tclass]);
+				}
+				response.push_tlv(packet::TLV_RECEIVED_SIZE, (size as u32).to_be_bytes().to_vec());
+				integrity::echo(&ping, &mut response, args.echo);
+				response.push_tlv(packet::TLV_RECEIVE_TIMESTAMP, received_at.to_be_bytes().to_vec());
+				response.push_tlv(packet::TLV_TRANSMIT_TIMESTAMP, clock.now_nanos().to_be_bytes().to_vec());
+				if info.truncated_from.is_some() {
+					response.flags |= packet::FLAG_TRUNCATED;
+				}
+				let response = response.encode();
+				let sent = pktinfo::send_to(&socket, &response, client_addr, info.if_index, info.reply_source());
+				match sent {
+					Ok(_) => counters.lock().unwrap().entry(interface).or_default().replies += 1,
+					Err(_) => counters.lock().unwrap().entry(interface).or_default().send_errors += 1,
+				}
+				if json::enabled() {
+					Object::event("request", received_at)
+						.field("count", packet_count)
+						.field("client", client_addr.to_string())
+						.field("session_id", format!("{:08x}", ping.session_id))
+						.field("seq", ping.seq)
+						.field("size", size)
+						.field("truncated", info.truncated_from.is_some())
+						.field("corrupted", corrupted)
+						.field("interface", info.if_index.map(iface::interface_name))
+						.field("destination", info.dst.map(|dst| dst.to_string()))
+						.field("sent_hop_limit", ping.tlv_u8(packet::TLV_HOP_LIMIT))
+						.field("hop_limit", info.hop_limit)
+						.field("sent_dscp", ping.tlv_u8(packet::TLV_TCLASS).map(qos::dscp))
+						.field("dscp", info.tclass.map(qos::dscp))
+						.field("replied", sent.is_ok())
+						.field("error", sent.as_ref().err().map(|e| e.to_string()))
+						.emit();
+				}
+				match sent {
+					Ok(_) if !json::enabled() => println!("[{}] Sent response to {}", packet_count, client_addr),
+					Ok(_) => {}
 					Err(e) => eprintln!("[{}] Failed to send response: {}", packet_count, e),
 				}
 			}
@@ -97,145 +400,720 @@ fn run_server(interface: Option<String>) -> io::Result<()> {
 	}
 }
 
-fn run_client(interval_ms: u64, interface: Option<String>) -> io::Result<()> {
+/// Returns whether the run met the loss threshold.
+fn run_client(args: &Args) -> io::Result<bool> {
+	let interval_ms = args.interval;
 	// Create UDP socket for sending
-	let send_socket = Socket::new(Domain::IPV6, Type::DGRAM, Some(Protocol::UDP))?;
+	let send_socket = Socket::new(Domain::for_address(args.group_addr()), Type::DGRAM, Some(Protocol::UDP))?;
 
 	// Bind to any available port for sending
-	let bind_addr = SocketAddrV6::new(Ipv6Addr::UNSPECIFIED, 0, 0, 0);
+	let bind_addr = SocketAddr::new(args.unspecified(), 0);
 	send_socket.bind(&bind_addr.into())?;
 
-	// Set multicast interface if specified
-	let interface_index = if let Some(ref if_name) = interface {
-		let idx = get_interface_index(if_name)?;
-		send_socket.set_multicast_if_v6(idx)?;
-		println!("Using interface index {} for multicast", idx);
-		idx
-	} else {
-		0
-	};
+	if args.interface.len() > 1 || args.all_interfaces {
+		return Err(io::Error::new(
+			ErrorKind::InvalidInput,
+			"The client sends on a single interface; pass --interface at most once",
+		));
+	}
 
-	// Create socket for receiving responses
-	let recv_socket = Socket::new(Domain::IPV6, Type::DGRAM, Some(Protocol::UDP))?;
-	recv_socket.set_reuse_address(true)?;
+	// Set multicast interface if specified
+	if let Some(if_name) = args.interface.first() {
+		match args.group {
+			IpAddr::V4(_) => {
+				// IP_MULTICAST_IF takes the interface's address rather than its index
+				let addr = iface::get_interface_ipv4(if_name)?;
+				send_socket.set_multicast_if_v4(&addr)?;
+				info!("Using interface address {} for multicast", addr);
+			}
+			IpAddr::V6(_) => {
+				let idx = iface::get_interface_index(if_name)?;
+				send_socket.set_multicast_if_v6(idx)?;
+				info!("Using interface index {} for multicast", idx);
+			}
+		}
+	}
 
 	// Get the local port we're bound to
 	let local_addr = send_socket.local_addr()?;
 	let local_port = match local_addr.as_socket() {
-		Some(SocketAddr::V6(addr)) => addr.port(),
-		Some(SocketAddr::V4(_)) => {
-			return Err(io::Error::new(ErrorKind::Other, "Expected IPv6 address"));
-		}
+		Some(addr) => addr.port(),
 		None => {
-			return Err(io::Error::new(ErrorKind::Other, "Invalid local address"));
+			return Err(io::Error::other("Invalid local address"));
 		}
 	};
 
-	// Bind receive socket to the same port
-	let recv_bind_addr = SocketAddrV6::new(Ipv6Addr::UNSPECIFIED, local_port, 0, 0);
-	recv_socket.bind(&recv_bind_addr.into())?;
+	// Receive responses on a handle to the same socket; the servers reply to the
+	// address the probe came from, and a second socket bound to the same port
+	// would not reliably get those unicast packets (or fail to bind at all)
+	let recv_socket = send_socket.try_clone()?;
 
-	// Set receive timeout
-	recv_socket.set_read_timeout(Some(Duration::from_millis(interval_ms / 2)))?;
+	info!("Bound to local port: {}", local_port);
 
-	println!("Bound to local port: {}", local_port);
+	if let Some(hops) = args.hops {
+		set_probe_hop_limit(&send_socket, args.group, hops)?;
+	}
+	if args.loopback || args.no_loopback {
+		match args.group {
+			IpAddr::V4(_) => send_socket.set_multicast_loop_v4(args.loopback)?,
+			IpAddr::V6(_) => send_socket.set_multicast_loop_v6(args.loopback)?,
+		}
+	}
 
-	let sent_count = Arc::new(AtomicU64::new(0));
-	let recv_count = Arc::new(AtomicU64::new(0));
+	if let Some(dscp) = args.dscp {
+		qos::set_dscp(&send_socket, args.group, dscp)?;
+		info!("Marking probes with DSCP {}", qos::dscp_name(dscp));
+	}
+	let probe_tclass = args.dscp.unwrap_or(0) << 2;
 
-	let recv_count_clone = Arc::clone(&recv_count);
-	let sent_count_clone = Arc::clone(&sent_count);
+	let base_hop_limit = match args.group {
+		IpAddr::V4(_) => send_socket.multicast_ttl_v4()?,
+		IpAddr::V6(_) => send_socket.multicast_hops_v6()?,
+	} as u8;
+	let ttl_sweep = args.ttl_sweep;
+	// Hop limit probe `seq` is sent with
+	let probe_hop_limit = move |seq: u64| match ttl_sweep {
+		Some(max) => (seq.saturating_sub(1) % u64::from(max)) as u8 + 1,
+		None => base_hop_limit,
+	};
+	if let Some(max) = ttl_sweep {
+		info!("Sweeping probe hop limit from 1 to {}", max);
+	}
+
+	let pmtu_sweep = args.pmtu_sweep;
+	let size_step = args.size_step as usize;
+	let fixed_size = args.size;
+	let sweep_steps = pmtu_sweep.map(|max| max.div_ceil(size_step) as u64);
+	// Size probe `seq` is padded to
+	let probe_size = move |seq: u64| match (pmtu_sweep, sweep_steps) {
+		(Some(max), Some(steps)) => {
+			Some(((seq.saturating_sub(1) % steps) as usize + 1).saturating_mul(size_step).min(max))
+		}
+		_ => fixed_size,
+	};
+	if let Some(max) = pmtu_sweep {
+		pmtu::set_dont_fragment(&send_socket, args.group)?;
+		info!("Sweeping probe size from {} to {} bytes in {}-byte steps, don't-fragment set",
+				 size_step.min(max), max, size_step);
+	}
+
+	let send_socket: UdpSocket = send_socket.into();
+	let recv_socket: UdpSocket = recv_socket.into();
+	pktinfo::enable(&recv_socket, args.group.is_ipv4())?;
+	let kernel_timestamps = enable_kernel_timestamps(&recv_socket, args.kernel_timestamps);
+	let kernel_timestamped = Arc::new(AtomicU64::new(0));
+	let kernel_timestamped_clone = Arc::clone(&kernel_timestamped);
+	let session_id = random_id();
+	let clock = Clock::new();
+
+	let table = Arc::new(Mutex::new(ResponderTable::new()));
+	let csv = match &args.csv {
+		Some(path) => {
+			info!("Writing per-probe results to {}", path.display());
+			Some(Arc::new(Mutex::new(CsvLog::create(path)?)))
+		}
+		None => None,
+	};
+	let csv_clone = csv.clone();
+
+	// Shared with the metrics endpoint
+	let probes_sent = Arc::new(AtomicU64::new(0));
+	let settled_seq = Arc::new(AtomicU64::new(0));
+	if let Some(addr) = args.metrics_listen {
+		let table = Arc::clone(&table);
+		let probes_sent = Arc::clone(&probes_sent);
+		let settled_seq = Arc::clone(&settled_seq);
+		metrics::serve(addr, move || {
+			let mut w = metrics::Writer::default();
+			w.family("multicast_ping_probes_sent_total", "counter", "Probes sent.");
+			w.sample("multicast_ping_probes_sent_total", &[], probes_sent.load(Ordering::Relaxed) as f64);
+			table.lock().unwrap().write_metrics(&mut w, settled_seq.load(Ordering::Relaxed));
+			w.finish()
+		})?;
+		info!("Serving metrics at http://{}/metrics", addr);
+	}
+
+	let table_clone = Arc::clone(&table);
+	let buffer_size = args.buffer_size;
+	let tui = args.tui;
+	let late_after_ms = args.late_after.unwrap_or(Duration::from_millis(interval_ms)).as_secs_f64() * 1000.0;
 
 	// Spawn receiver thread
-	let recv_handle = std::thread::spawn(move || {
-		let mut buf = [0u8; BUFFER_SIZE];
+	std::thread::spawn(move || {
+		let mut buf = vec![0u8; buffer_size];
 		loop {
-			match recv_socket.recv_from(&mut buf) {
-				Ok((len, addr)) => {
-					let count = recv_count_clone.fetch_add(1, Ordering::SeqCst) + 1;
-					let msg = String::from_utf8_lossy(&buf[..len]);
-					if let Some(socket_addr) = addr.as_socket() {
-						println!("← Received response #{} from {}: {}", count, socket_addr, msg);
+			match pktinfo::recv(&recv_socket, &mut buf) {
+				Ok(info) => {
+					let socket_addr = info.source;
+					let decoded = match info.truncated_from {
+						Some(_) => Packet::decode_truncated(&buf[..info.len]),
+						None => Packet::decode(&buf[..info.len]),
+					};
+					let pong = match decoded {
+						// Probes are numbered from 1
+						Ok(p) if p.msg_type == MessageType::Pong && p.session_id == session_id && p.seq > 0 => p,
+						Ok(_) => continue,
+						Err(e) => {
+							report!("Invalid response from {}: {}", socket_addr, e);
+							continue;
+						}
+					};
+					let now = match info.timestamp {
+						Some(timestamp) => {
+							kernel_timestamped_clone.fetch_add(1, Ordering::Relaxed);
+							clock.adjust_wall(timestamp)
+						}
+						None => clock.now_nanos(),
+					};
+					let rtt_ms = now.saturating_sub(pong.timestamp) as f64 / 1_000_000.0;
+					let exchange = match (pong.tlv_u64(packet::TLV_RECEIVE_TIMESTAMP), pong.tlv_u64(packet::TLV_TRANSMIT_TIMESTAMP)) {
+						(Some(t2), Some(t3)) => Some(Exchange::new(pong.timestamp, t2, t3, now)),
+						_ => None,
+					};
+					let key = ResponderKey { addr: socket_addr, server_id: pong.server_id() };
+					let hop_limit = probe_hop_limit(pong.seq);
+					let received_size = pong.tlv_u32(packet::TLV_RECEIVED_SIZE).map(|size| size as usize);
+					let truncated = pong.flags & packet::FLAG_TRUNCATED != 0;
+					let verdict = integrity::verify(&pong, info.truncated_from.is_some());
+					let (event, class, closer, count) = {
+						let mut table = table_clone.lock().unwrap();
+						let (event, class) = table.record_reply(key, pong.seq, rtt_ms, rtt_ms > late_after_ms);
+						let closer = table.record_reach(key, hop_limit);
+						if let Some(size) = received_size.filter(|_| !truncated) {
+							table.record_size(key, size);
+						}
+						table.record_verdict(key, verdict);
+						if let Some(exchange) = exchange.filter(|_| class != ReplyClass::Duplicate) {
+							table.record_exchange(key, exchange);
+						}
+						table.record_arrival(key, now, info.if_index.map(iface::interface_name));
+						(event, class, closer, table.total_received())
+					};
+					if let Some(event) = event
+						&& !tui
+					{
+						print_event(&clock, &key, event);
 					}
-				}
-				Err(e) if e.kind() == ErrorKind::WouldBlock || e.kind() == ErrorKind::TimedOut => {
-					// Timeout is expected, continue
+					if let Some(csv) = &csv_clone {
+						let status = match verdict {
+							Verdict::Corrupted | Verdict::Truncated => verdict.name(),
+							Verdict::Intact | Verdict::Unchecked => class.name(),
+						};
+						write_csv(csv, &csv::Row {
+							time: now,
+							seq: pong.seq,
+							key: &key,
+							status,
+							rtt_ms: Some(rtt_ms),
+							hop_limit: pong.tlv_u8(packet::TLV_RECEIVED_HOP_LIMIT),
+							payload_size: received_size,
+						});
+					}
+					if tui {
+						continue;
+					}
+					if json::enabled() {
+						key.json_fields(Object::event("reply", now))
+							.field("count", count)
+							.field("seq", pong.seq)
+							.field("class", class.name())
+							.field("rtt_ms", rtt_ms)
+							.field("timestamp_source", if info.timestamp.is_some() { "kernel" } else { "userspace" })
+							.field("interface", info.if_index.map(iface::interface_name))
+							.field("destination", info.dst.map(|dst| dst.to_string()))
+							.field("forward_ms", exchange.map(|e| e.forward))
+							.field("reverse_ms", exchange.map(|e| e.reverse))
+							.field("clock_offset_ms", exchange.map(|e| e.offset))
+							.field("probe_hop_limit", hop_limit)
+							.field("probe_received_hop_limit", pong.tlv_u8(packet::TLV_RECEIVED_HOP_LIMIT))
+							.field("reply_hop_limit", pong.tlv_u8(packet::TLV_HOP_LIMIT))
+							.field("reply_received_hop_limit", info.hop_limit)
+							.field("probe_dscp", qos::dscp(probe_tclass))
+							.field("probe_received_dscp", pong.tlv_u8(packet::TLV_RECEIVED_TCLASS).map(qos::dscp))
+							.field("reply_dscp", pong.tlv_u8(packet::TLV_TCLASS).map(qos::dscp))
+							.field("reply_received_dscp", info.tclass.map(qos::dscp))
+							.field("probe_size", received_size)
+							.field("payload", verdict.name())
+							.emit();
+						continue;
+					}
+					if closer && ttl_sweep.is_some() {
+						println!("↳ {} reachable with hop limit {}", key, hop_limit);
+					}
+					let size = match (probe_size(pong.seq), received_size) {
+						(Some(_), Some(received)) if !truncated => format!(" size={}", received),
+						_ => String::new(),
+					};
+					let class = match class {
+						ReplyClass::OnTime => String::new(),
+						class => format!(" ({})", class),
+					};
+					println!("← Received response #{} from {} {}: seq={}{}{} time={:.3} ms{}, probe {}, reply {}{}{}{}",
+							 count, key, describe_arrival(&info), pong.seq, class, size, rtt_ms,
+							 exchange.map(describe_exchange).unwrap_or_default(),
+							 describe_hops(Some(hop_limit), pong.tlv_u8(packet::TLV_RECEIVED_HOP_LIMIT)),
+							 describe_hops(pong.tlv_u8(packet::TLV_HOP_LIMIT), info.hop_limit),
+							 describe_remarking((Some(probe_tclass), pong.tlv_u8(packet::TLV_RECEIVED_TCLASS)),
+												(pong.tlv_u8(packet::TLV_TCLASS), info.tclass)),
+							 describe_truncation(truncated.then_some(received_size).flatten(), info.truncated_from),
+							 if verdict == Verdict::Corrupted { ", ⚠ payload corrupted" } else { "" });
 				}
 				Err(e) => {
-					eprintln!("Receive error: {}", e);
+					report!("Receive error: {}", e);
 				}
 			}
 		}
 	});
 
+	let pusher = args.push.clone().map(|endpoint| {
+		info!("Pushing {:?} statistics to {} every {:?}", args.push_format, endpoint, args.push_interval);
+		Pusher::spawn(endpoint, args.push_format)
+	});
+	let group_name = args.group.to_string();
+	let mut last_push = Instant::now();
+
+	// Ctrl-C ends the run early but still prints the summary
+	let (stop_tx, stop_rx) = mpsc::channel();
+	ctrlc::set_handler(move || {
+		let _ = stop_tx.send(());
+	})
+	.map_err(io::Error::other)?;
+
 	// Main sending loop
-	let multicast_target = SocketAddrV6::new(MULTICAST_ADDR, PORT, 0, 0);
+	let multicast_target = args.group_addr();
 	let interval = Duration::from_millis(interval_ms);
 	let start_time = Instant::now();
+	let deadline = args.deadline.map(|d| start_time + d);
+	let sweep_length = ttl_sweep.map(u64::from).or(sweep_steps);
+	let max_count = match (args.count, sweep_length) {
+		(None, Some(steps)) if deadline.is_none() => Some(steps),
+		(count, _) => count,
+	};
+
+	// Restores the terminal however the loop below is left
+	let screen = tui.then(tui::Screen::enter);
+
+	let mut count = 0u64;
+	// Highest sequence number that has had a full interval to be answered
+	let mut settled = 0u64;
+	// Smallest probe that couldn't be sent without fragmenting it
+	let mut too_big: Option<usize> = None;
+	// Shown on the dashboard until the next probe is sent
+	let mut send_error: Option<String> = None;
 
 	loop {
-		let count = sent_count.fetch_add(1, Ordering::SeqCst) + 1;
-		let message = format!("PING:{}", count);
+		count += 1;
 
-		match send_socket.send_to(message.as_bytes(), &multicast_target.into()) {
-			Ok(_) => {
-				let received = recv_count.load(Ordering::SeqCst);
-				let percent = if count > 0 {
-					(received as f64 / count as f64) * 100.0
-				} else {
-					0.0
-				};
+		let lost = table.lock().unwrap().check_lost(settled, args.lost_after);
+		for (key, event) in lost.into_iter().filter(|_| !tui) {
+			print_event(&clock, &key, event);
+		}
+
+		// Stamped just before sending, once the probe is padded and encoded
+		let mut message = Packet::new(MessageType::Ping, session_id, count, 0);
+		let hop_limit = probe_hop_limit(count);
+		if ttl_sweep.is_some() {
+			set_probe_hop_limit(&SockRef::from(&send_socket), args.group, hop_limit)?;
+		}
+		message.push_tlv(packet::TLV_HOP_LIMIT, vec![hop_limit]);
+		message.push_tlv(packet::TLV_TCLASS, vec![probe_tclass]);
+		if let Some(size) = probe_size(count) {
+			message.pad_to(size);
+		}
+		let mut message = message.encode();
 
+		packet::set_timestamp(&mut message, clock.now_nanos());
+		let sent = send_socket.send_to(&message, multicast_target);
+		match &sent {
+			Ok(_) => {
+				probes_sent.fetch_add(1, Ordering::Relaxed);
+			}
+			// Other send errors, e.g. the link going down, still count as loss
+			Err(e) if pmtu::is_too_big(e) => table.lock().unwrap().record_unsent(count),
+			Err(_) => {}
+		}
+		match sent {
+			result if tui => {
+				let error = result.err();
+				if error.as_ref().is_some_and(pmtu::is_too_big) {
+					too_big = Some(too_big.map_or(message.len(), |size| size.min(message.len())));
+				}
+				send_error = error.map(|e| format!("Send error on probe #{}: {}", count, e));
+			}
+			Ok(_) if json::enabled() => {
+				let now = clock.now_nanos();
+				Object::event("probe", now)
+					.field("session_id", format!("{:08x}", session_id))
+					.field("seq", count)
+					.field("size", message.len())
+					.field("hop_limit", hop_limit)
+					.field("dscp", qos::dscp(probe_tclass))
+					.emit();
+				let table = table.lock().unwrap();
+				let object = Object::event("stats", now)
+					.field("probes_sent", count)
+					.field("responders", table.responders.len())
+					.field("replies", table.total_received())
+					.field("loss_pct", table.loss_percent(count - 1))
+					.field("max_jitter_ms", table.max_jitter())
+					.field("runtime_s", start_time.elapsed().as_secs_f64());
+				table.overall.json_fields(object, "rtt").field("per_responder", table.to_json(count - 1)).emit();
+			}
+			Ok(_) => {
 				let elapsed = start_time.elapsed().as_secs();
-				println!("→ Sent multicast #{} | Responses: {}/{} ({:.1}%) | Runtime: {}s",
-						 count, received, count, percent, elapsed);
+				let table = table.lock().unwrap();
+				println!("→ Sent multicast #{} | Responders: {} | Replies: {} | {} | Max jitter: {:.3} ms | Runtime: {}s",
+						 count, table.responders.len(), table.total_received(), table.overall,
+						 table.max_jitter(), elapsed);
+				// The probe just sent hasn't had a chance to be answered yet
+				table.print(count - 1);
+			}
+			Err(e) if json::enabled() => {
+				Object::event("send_error", clock.now_nanos())
+					.field("seq", count)
+					.field("size", message.len())
+					.field("needs_fragmentation", pmtu::is_too_big(&e))
+					.field("error", e.to_string())
+					.emit();
+				if pmtu::is_too_big(&e) {
+					too_big = Some(too_big.map_or(message.len(), |size| size.min(message.len())));
+				}
+			}
+			Err(e) if pmtu::is_too_big(&e) => {
+				eprintln!("Send error: probe #{} of {} bytes needs fragmentation ({})", count, message.len(), e);
+				too_big = Some(too_big.map_or(message.len(), |size| size.min(message.len())));
 			}
 			Err(e) => {
 				eprintln!("Send error: {}", e);
 			}
 		}
 
-		std::thread::sleep(interval);
+		let mut wait = interval;
+		if let Some(deadline) = deadline {
+			wait = wait.min(deadline.saturating_duration_since(Instant::now()));
+		}
+		// The dashboard keeps refreshing while waiting for the next probe
+		let wait_until = Instant::now() + wait;
+		let stopped = loop {
+			let remaining = wait_until.saturating_duration_since(Instant::now());
+			if tui {
+				let header = format!("Multicast ping {} | Probes: {} | Runtime: {}s | Ctrl-C to quit",
+									 multicast_target, count, start_time.elapsed().as_secs());
+				tui::draw(&header, &table.lock().unwrap(), clock.now_nanos(), count - 1, send_error.as_deref());
+			}
+			match stop_rx.recv_timeout(if tui { remaining.min(tui::REFRESH) } else { remaining }) {
+				Err(RecvTimeoutError::Timeout) if Instant::now() >= wait_until => break false,
+				Err(RecvTimeoutError::Timeout) => {}
+				_ => break true,
+			}
+		};
+		if stopped {
+			break;
+		}
+		if wait == interval {
+			settled = count;
+			settled_seq.store(settled, Ordering::Relaxed);
+			if let Some(csv) = &csv {
+				// Probes still unanswered after a full interval; replies that
+				// arrive later get a row of their own
+				let table = table.lock().unwrap();
+				let unsent = table.unsent.contains(&settled);
+				let unanswered = table
+					.responders
+					.iter()
+					.filter(|(_, r)| !unsent && r.first_seq <= settled && !r.answered(settled));
+				for (key, _) in unanswered {
+					write_csv(csv, &csv::Row {
+						time: clock.now_nanos(),
+						seq: settled,
+						key,
+						status: "lost",
+						rtt_ms: None,
+						hop_limit: None,
+						payload_size: Some(message.len()),
+					});
+				}
+			}
+		}
+
+		if let Some(pusher) = &pusher
+			&& last_push.elapsed() >= args.push_interval
+		{
+			last_push = Instant::now();
+			pusher.push(&group_name, &table.lock().unwrap(), settled, clock.now_nanos());
+		}
+
+		let deadline_passed = deadline.is_some_and(|d| Instant::now() >= d);
+		let count_reached = max_count.is_some_and(|c| count >= c);
+		if deadline_passed || count_reached {
+			break;
+		}
+	}
+
+	drop(screen);
+	let table = table.lock().unwrap();
+	if let Some(pusher) = pusher {
+		pusher.finish(&group_name, &table, settled, clock.now_nanos());
+	}
+	let kernel_timestamped = kernel_timestamped.load(Ordering::Relaxed);
+	if json::enabled() {
+		let object = Object::event("summary", clock.now_nanos())
+			.field("group", args.group_addr().to_string())
+			.field("session_id", format!("{:08x}", session_id))
+			.field("probes_sent", count)
+			.field("responders", table.responders.len())
+			.field("replies", table.total_received())
+			.field("loss_pct", table.loss_percent(settled))
+			.field("time_ms", start_time.elapsed().as_millis() as u64)
+			.field("max_jitter_ms", table.max_jitter())
+			.field("kernel_timestamped", kernel_timestamped)
+			.field("needs_fragmentation_from", too_big);
+		table.overall.json_fields(object, "rtt").field("per_responder", table.to_json(settled)).emit();
+	} else {
+		print_summary(args, &table, count, settled, too_big, start_time.elapsed());
+	}
+	if kernel_timestamps && !json::enabled() {
+		println!("Receive timestamps: kernel for {} of {} replies, userspace for the rest",
+				 kernel_timestamped, table.total_received());
 	}
+
+	let ok = match args.max_loss {
+		Some(max_loss) => table.responders.values().all(|r| r.loss_percent(settled, &table.unsent) <= max_loss),
+		None => true,
+	} && table.total_received() > 0;
+	Ok(ok)
 }
 
-fn get_interface_index(if_name: &str) -> io::Result<u32> {
-	#[cfg(unix)]
-	{
-		use std::ffi::CString;
-		let c_name = CString::new(if_name)
-			.map_err(|_| io::Error::new(ErrorKind::InvalidInput, "Invalid interface name"))?;
-
-		let index = unsafe { libc::if_nametoindex(c_name.as_ptr()) };
-		if index == 0 {
-			return Err(io::Error::new(
-				ErrorKind::NotFound,
-				format!("Interface '{}' not found", if_name),
-			));
-		}
-		Ok(index)
-	}
-
-	#[cfg(windows)]
-	{
-		use std::net::UdpSocket;
-		// On Windows, try to get the interface index
-		// This is a simplified approach - on Windows you may need to use
-		// the Windows API (GetAdaptersAddresses) for better interface lookup
-
-		// Try to parse as a number first (Windows sometimes uses indices directly)
-		if let Ok(index) = if_name.parse::<u32>() {
-			return Ok(index);
-		}
-
-		// Otherwise, return error with helpful message
-		Err(io::Error::new(
-			ErrorKind::NotFound,
-			format!(
-				"Interface '{}' lookup not fully supported on Windows. \
-				Try using the interface index number instead (find with 'netsh interface ipv6 show interface')",
-				if_name
-			),
-		))
+fn print_summary(args: &Args, table: &ResponderTable, sent: u64, settled: u64, too_big: Option<usize>, elapsed: Duration) {
+	println!();
+	println!("--- {} multicast ping statistics ---", args.group_addr());
+	let responders = table.responders.values();
+	let classes: Vec<String> = [
+		(responders.clone().map(|r| r.late).sum::<u64>(), "late"),
+		(responders.clone().map(|r| r.duplicates).sum(), "duplicate"),
+		(responders.map(|r| r.out_of_order).sum(), "out of order"),
+		(table.total_corrupted(), "corrupted"),
+		(table.total_truncated(), "truncated"),
+	]
+	.into_iter()
+	.filter(|(count, _)| *count > 0)
+	.map(|(count, class)| format!("{} {}", count, class))
+	.collect();
+	let breakdown = if classes.is_empty() { String::new() } else { format!(" ({})", classes.join(", ")) };
+	println!("{} probes sent, {} responders, {} replies{}, {:.1}% loss, time {}ms",
+			 sent, table.responders.len(), table.total_received(), breakdown,
+			 table.loss_percent(settled), elapsed.as_millis());
+	if table.overall.count() > 0 {
+		println!("{}", table.overall);
+		println!("jitter max = {:.3} ms", table.max_jitter());
+	}
+	table.print(settled);
+
+	if args.ttl_sweep.is_some() {
+		println!("Hop limit needed to reach each responder:");
+		for (key, responder) in &table.responders {
+			if let Some(hop_limit) = responder.min_hop_limit {
+				println!("    {:<48} {}", key.to_string(), hop_limit);
+			}
+		}
+	}
+
+	if table.responders.values().any(|r| r.best_exchange.is_some()) {
+		println!("One-way delays (forward/reverse avg, including clock offset) and server clock offset:");
+		for (key, responder) in &table.responders {
+			if let Some(best) = responder.best_exchange {
+				println!("    {:<48} {:.3}/{:.3} ms, offset {:+.3} ms",
+						 key.to_string(), responder.forward.avg(), responder.reverse.avg(), best.offset);
+			}
+		}
+	}
+
+	if args.pmtu_sweep.is_some() {
+		if let Some(size) = too_big {
+			println!("Probes of {} bytes and more need fragmentation on the local link", size);
+		}
+		println!("Largest probe received by each responder:");
+		for (key, responder) in &table.responders {
+			let truncated = if responder.truncated > 0 { " (larger probes truncated by its receive buffer)" } else { "" };
+			match responder.max_size {
+				Some(size) => println!("    {:<48} {} bytes{}", key.to_string(), size, truncated),
+				None => println!("    {:<48} none{}", key.to_string(), truncated),
+			}
+		}
+	}
+}
+
+fn write_csv(csv: &Mutex<CsvLog>, row: &csv::Row) {
+	if let Err(e) = csv.lock().unwrap().write(row) {
+		report!("CSV write error: {}", e);
+	}
+}
+
+/// Turn on kernel receive timestamps if `requested`, reporting which source
+/// will be used. Returns whether kernel timestamps are on.
+fn enable_kernel_timestamps(socket: &UdpSocket, requested: bool) -> bool {
+	if !requested {
+		info!("Receive timestamps: userspace");
+		return false;
+	}
+	match pktinfo::enable_timestamps(socket) {
+		Ok(()) => {
+			info!("Receive timestamps: kernel (SO_TIMESTAMPNS)");
+			true
+		}
+		Err(e) => {
+			eprintln!("Warning: kernel timestamps unavailable ({}), falling back to userspace", e);
+			false
+		}
+	}
+}
+
+fn set_probe_hop_limit(socket: &Socket, group: IpAddr, hop_limit: u8) -> io::Result<()> {
+	match group {
+		IpAddr::V4(_) => socket.set_multicast_ttl_v4(hop_limit.into()),
+		IpAddr::V6(_) => socket.set_multicast_hops_v6(hop_limit.into()),
+	}
+}
+
+fn print_event(clock: &Clock, key: &ResponderKey, event: ResponderEvent) {
+	if json::enabled() {
+		let missed = match event {
+			ResponderEvent::New => None,
+			ResponderEvent::Lost { missed } | ResponderEvent::Recovered { missed } => Some(missed),
+		};
+		key.json_fields(Object::event(event.name(), clock.now_nanos())).field("missed", missed).emit();
+		return;
+	}
+	let marker = match event {
+		ResponderEvent::New => "+",
+		ResponderEvent::Lost { .. } => "✗",
+		ResponderEvent::Recovered { .. } => "✓",
+	};
+	println!("{} [{}] {}: {}", marker, clock::format_rfc3339(clock.now_nanos()), key, event);
+}
+
+/// "on eth0 to ff15::1" for a received datagram, as far as it is known.
+fn describe_arrival(info: &pktinfo::RecvInfo) -> String {
+	let interface = match info.if_index {
+		Some(index) => iface::interface_name(index),
+		None => "unknown interface".to_string(),
+	};
+	match info.dst {
+		Some(dst) => format!("on {} to {}", interface, dst),
+		None => format!("on {}", interface),
+	}
+}
+
+/// "hop limit 62 (2 hops)" from the hop limit a packet was sent with and the one it arrived with.
+fn describe_hops(sent: Option<u8>, received: Option<u8>) -> String {
+	match (sent, received) {
+		(Some(sent), Some(received)) => {
+			let hops = sent.saturating_sub(received);
+			format!("hop limit {} ({} hop{})", received, hops, if hops == 1 { "" } else { "s" })
+		}
+		(None, Some(received)) => format!("hop limit {}", received),
+		_ => "hop limit ?".to_string(),
+	}
+}
+
+/// ", ⚠ probe re-marked EF → CS0" for each leg whose DSCP changed on the way, or nothing.
+fn describe_remarking(probe: (Option<u8>, Option<u8>), reply: (Option<u8>, Option<u8>)) -> String {
+	let mut out = String::new();
+	for (leg, (sent, received)) in [("probe", probe), ("reply", reply)] {
+		if let Some(remarked) = qos::remarking(sent, received) {
+			out.push_str(&format!(", ⚠ {} re-marked {}", leg, remarked));
+		}
+	}
+	out
+}
+
+/// " (fwd 0.120 ms, rev 0.180 ms, offset -0.030 ms)" for an exchange.
+fn describe_exchange(exchange: Exchange) -> String {
+	format!(" (fwd {:.3} ms, rev {:.3} ms, offset {:+.3} ms)", exchange.forward, exchange.reverse, exchange.offset)
+}
+
+/// ", ⚠ 1400-byte probe truncated by the server" if the server's or our own
+/// receive buffer cut a datagram short, or nothing.
+fn describe_truncation(probe: Option<usize>, reply: Option<usize>) -> String {
+	let mut out = String::new();
+	if let Some(size) = probe {
+		out.push_str(&format!(", ⚠ {}-byte probe truncated by the server", size));
+	}
+	if let Some(size) = reply {
+		out.push_str(&format!(", ⚠ {}-byte reply truncated, raise --buffer-size", size));
+	}
+	out
+}
+
+/// Parse a duration such as `500ms`, `30s`, `15m` or `2h`; a plain number is seconds.
+fn parse_duration(value: &str) -> Result<Duration, String> {
+	let value = value.trim();
+	let split = value.find(|c: char| !c.is_ascii_digit() && c != '.').unwrap_or(value.len());
+	let (number, unit) = value.split_at(split);
+	let number: f64 = number.parse().map_err(|_| format!("invalid duration '{}'", value))?;
+	let secs = match unit {
+		"ms" => number / 1000.0,
+		"" | "s" => number,
+		"m" => number * 60.0,
+		"h" => number * 3600.0,
+		_ => return Err(format!("unknown duration unit '{}' (use ms, s, m or h)", unit)),
+	};
+	Duration::try_from_secs_f64(secs).map_err(|e| format!("invalid duration '{}': {}", value, e))
+}
+
+fn parse_loss_percent(value: &str) -> Result<f64, String> {
+	let percent: f64 = value.parse().map_err(|_| format!("invalid percentage '{}'", value))?;
+	if !(0.0..=100.0).contains(&percent) {
+		return Err("loss percentage must be between 0 and 100".to_string());
+	}
+	Ok(percent)
+}
+
+fn parse_buffer_size(value: &str) -> Result<usize, String> {
+	let size: usize = value.parse().map_err(|_| format!("invalid buffer size '{}'", value))?;
+	if !(packet::HEADER_LEN..=65535).contains(&size) {
+		return Err(format!("buffer size must be between {} and 65535 bytes", packet::HEADER_LEN));
+	}
+	Ok(size)
+}
+
+fn parse_probe_size(value: &str) -> Result<usize, String> {
+	let size: usize = value.parse().map_err(|_| format!("invalid probe size '{}'", value))?;
+	if !(packet::MIN_PROBE_LEN..=pmtu::MAX_UDP_PAYLOAD).contains(&size) {
+		return Err(format!("probe size must be between {} and {} bytes", packet::MIN_PROBE_LEN, pmtu::MAX_UDP_PAYLOAD));
+	}
+	Ok(size)
+}
+
+/// Random-enough identifier for client sessions and servers.
+fn random_id() -> u32 {
+	let nanos = clock::wall_nanos();
+	(nanos as u32) ^ ((nanos >> 32) as u32) ^ std::process::id().rotate_left(16)
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn durations() {
+		assert_eq!(parse_duration("30"), Ok(Duration::from_secs(30)));
+		assert_eq!(parse_duration("250ms"), Ok(Duration::from_millis(250)));
+		assert_eq!(parse_duration("1.5s"), Ok(Duration::from_millis(1500)));
+		assert_eq!(parse_duration("15m"), Ok(Duration::from_secs(900)));
+		assert_eq!(parse_duration("2h"), Ok(Duration::from_secs(7200)));
+		assert!(parse_duration("").is_err());
+		assert!(parse_duration("5d").is_err());
+		assert!(parse_duration("ms").is_err());
+	}
+
+	#[test]
+	fn loss_percentages() {
+		assert_eq!(parse_loss_percent("0"), Ok(0.0));
+		assert_eq!(parse_loss_percent("12.5"), Ok(12.5));
+		assert_eq!(parse_loss_percent("100"), Ok(100.0));
+		assert!(parse_loss_percent("-1").is_err());
+		assert!(parse_loss_percent("100.1").is_err());
+		assert!(parse_loss_percent("NaN").is_err());
 	}
 }
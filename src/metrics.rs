@@ -0,0 +1,139 @@
+//! Prometheus metrics endpoint (`--metrics-listen`).
+//!
+//! A minimal HTTP/1.1 server that answers `GET /metrics` with the text
+//! exposition format; everything else gets a 404.
+
+use crate::json::report;
+use std::collections::BTreeMap;
+use std::fmt::Write as _;
+use std::io::{self, BufRead, BufReader, Read, Write};
+use std::net::{SocketAddr, TcpListener, TcpStream};
+use std::sync::Arc;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::Duration;
+
+/// How long a scraper may take to send its request or read the response
+/// before its connection is dropped.
+const CONNECTION_TIMEOUT: Duration = Duration::from_secs(5);
+/// Most bytes of request line and headers read from one connection.
+const MAX_REQUEST_LEN: u64 = 8 * 1024;
+/// Connections served at once; further ones are closed straight away.
+const MAX_CONNECTIONS: usize = 16;
+
+/// Builds a page in the Prometheus text exposition format.
+#[derive(Debug, Default)]
+pub struct Writer {
+	out: String,
+}
+
+impl Writer {
+	/// Start a metric family with its `# HELP` and `# TYPE` lines.
+	pub fn family(&mut self, name: &str, kind: &str, help: &str) {
+		let _ = writeln!(self.out, "# HELP {} {}", name, help);
+		let _ = writeln!(self.out, "# TYPE {} {}", name, kind);
+	}
+
+	pub fn sample(&mut self, name: &str, labels: &[(&str, &str)], value: f64) {
+		self.out.push_str(name);
+		if !labels.is_empty() {
+			self.out.push('{');
+			for (i, (label, value)) in labels.iter().enumerate() {
+				if i > 0 {
+					self.out.push(',');
+				}
+				let value = value.replace('\\', "\\\\").replace('"', "\\\"").replace('\n', "\\n");
+				let _ = write!(self.out, "{}=\"{}\"", label, value);
+			}
+			self.out.push('}');
+		}
+		if value.is_infinite() {
+			let _ = writeln!(self.out, " {}Inf", if value > 0.0 { "+" } else { "-" });
+		} else {
+			let _ = writeln!(self.out, " {}", value);
+		}
+	}
+
+	pub fn finish(self) -> String {
+		self.out
+	}
+}
+
+/// Serve `render()` at `/metrics` on `addr` from a background thread.
+pub fn serve<F>(addr: SocketAddr, render: F) -> io::Result<()>
+where
+	F: Fn() -> String + Send + Sync + 'static,
+{
+	let listener = TcpListener::bind(addr)?;
+	let render = Arc::new(render);
+	let open = Arc::new(AtomicUsize::new(0));
+	std::thread::spawn(move || {
+		for stream in listener.incoming() {
+			if open.load(Ordering::Relaxed) >= MAX_CONNECTIONS {
+				continue;
+			}
+			open.fetch_add(1, Ordering::Relaxed);
+			let render = Arc::clone(&render);
+			let open = Arc::clone(&open);
+			// One thread per connection so a slow scraper doesn't hold up the others
+			std::thread::spawn(move || {
+				if let Err(e) = stream.and_then(|stream| respond(stream, &*render)) {
+					report!("Metrics connection error: {}", e);
+				}
+				open.fetch_sub(1, Ordering::Relaxed);
+			});
+		}
+	});
+	Ok(())
+}
+
+fn respond<F: Fn() -> String>(mut stream: TcpStream, render: &F) -> io::Result<()> {
+	stream.set_read_timeout(Some(CONNECTION_TIMEOUT))?;
+	stream.set_write_timeout(Some(CONNECTION_TIMEOUT))?;
+	let mut reader = BufReader::new(stream.try_clone()?.take(MAX_REQUEST_LEN));
+	let mut request_line = String::new();
+	reader.read_line(&mut request_line)?;
+	// Skip the headers; requests to /metrics have no body
+	let mut header = String::new();
+	while reader.read_line(&mut header)? > 2 {
+		header.clear();
+	}
+
+	let mut parts = request_line.split_whitespace();
+	let (status, content_type, body) = match (parts.next(), parts.next()) {
+		(Some("GET"), Some("/metrics")) => ("200 OK", "text/plain; version=0.0.4; charset=utf-8", render()),
+		_ => ("404 Not Found", "text/plain; charset=utf-8", "Not found; try /metrics\n".to_string()),
+	};
+	write!(
+		stream,
+		"HTTP/1.1 {}\r\nContent-Type: {}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+		status,
+		content_type,
+		body.len(),
+		body
+	)?;
+	stream.flush()
+}
+
+/// Server request counters for one interface.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct InterfaceCounters {
+	pub requests: u64,
+	pub replies: u64,
+	pub send_errors: u64,
+}
+
+/// Add the server's per-interface counters to `w`.
+pub fn write_server_metrics(w: &mut Writer, interfaces: &BTreeMap<String, InterfaceCounters>) {
+	w.family("multicast_ping_requests_received_total", "counter", "Probes received.");
+	for (interface, counters) in interfaces {
+		w.sample("multicast_ping_requests_received_total", &[("interface", interface)], counters.requests as f64);
+	}
+	w.family("multicast_ping_replies_sent_total", "counter", "Replies sent.");
+	for (interface, counters) in interfaces {
+		w.sample("multicast_ping_replies_sent_total", &[("interface", interface)], counters.replies as f64);
+	}
+	w.family("multicast_ping_send_errors_total", "counter", "Replies that failed to send.");
+	for (interface, counters) in interfaces {
+		w.sample("multicast_ping_send_errors_total", &[("interface", interface)], counters.send_errors as f64);
+	}
+}
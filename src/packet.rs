@@ -0,0 +1,413 @@
+//! Binary wire format shared by the client and server.
+//!
+//! Every datagram starts with a fixed 32-byte header followed by zero or more
+//! TLV (type, length, value) records. All integers are big-endian.
+//!
+//! ```text
+//!  0               1               2               3
+//!  0 1 2 3 4 5 6 7 0 1 2 3 4 5 6 7 0 1 2 3 4 5 6 7 0 1 2 3 4 5 6 7
+//! +---------------------------------------------------------------+
+//! |                         magic "MCPG"                          |
+//! +---------------+---------------+-------------------------------+
+//! |    version    |  message type |             flags             |
+//! +---------------+---------------+-------------------------------+
+//! |                       client session id                       |
+//! +---------------------------------------------------------------+
+//! |                        sequence number                        |
+//! |                           (64 bit)                            |
+//! +---------------------------------------------------------------+
+//! |                        send timestamp                         |
+//! |              (64 bit, nanoseconds since UNIX epoch)           |
+//! +---------------------------------------------------------------+
+//! |                         reserved (0)                          |
+//! +---------------------------------------------------------------+
+//! |   TLV type (16 bit)           |   TLV length (16 bit)         |
+//! +-------------------------------+-------------------------------+
+//! |                     TLV value (length bytes)                  |
+//! +---------------------------------------------------------------+
+//! ```
+//!
+//! New optional fields are added as TLVs. Decoders keep TLVs they don't
+//! understand, so older peers keep working with newer ones. The version is
+//! only bumped for changes to the fixed header.
+
+use std::fmt;
+
+pub const MAGIC: [u8; 4] = *b"MCPG";
+pub const VERSION: u8 = 1;
+pub const HEADER_LEN: usize = 32;
+const TLV_HEADER_LEN: usize = 4;
+/// Smallest padded probe: the header, the hop limit and traffic class TLVs
+/// every probe carries and an empty padding TLV.
+pub const MIN_PROBE_LEN: usize = HEADER_LEN + 2 * (TLV_HEADER_LEN + 1) + TLV_HEADER_LEN;
+
+/// Random id a server picks at startup, carried in its replies (u32)
+pub const TLV_SERVER_ID: u16 = 1;
+/// Hop limit / TTL the packet was sent with (u8)
+pub const TLV_HOP_LIMIT: u16 = 2;
+/// Hop limit / TTL the probe had left when it reached the server, in replies (u8)
+pub const TLV_RECEIVED_HOP_LIMIT: u16 = 3;
+/// Traffic class / TOS byte the packet was sent with (u8)
+pub const TLV_TCLASS: u16 = 4;
+/// Traffic class / TOS byte the probe arrived with, in replies (u8)
+pub const TLV_RECEIVED_TCLASS: u16 = 5;
+/// Filler bringing a probe up to the requested size; see `padding_byte` (bytes)
+pub const TLV_PADDING: u16 = 6;
+/// Length of the probe as it arrived at the server, in replies (u32)
+pub const TLV_RECEIVED_SIZE: u16 = 7;
+/// Length (u32) and CRC-32 (u32) of the padding the server received, in replies
+pub const TLV_PAYLOAD_CHECKSUM: u16 = 8;
+/// Time the server received the probe, in replies (u64, ns since the UNIX epoch)
+pub const TLV_RECEIVE_TIMESTAMP: u16 = 9;
+/// Time the server sent the reply (u64, ns since the UNIX epoch)
+pub const TLV_TRANSMIT_TIMESTAMP: u16 = 10;
+
+/// Set in a reply when the probe didn't fit the server's receive buffer
+pub const FLAG_TRUNCATED: u16 = 0x0001;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MessageType {
+	/// Multicast probe sent by the client
+	Ping,
+	/// Unicast reply sent by the server
+	Pong,
+}
+
+impl MessageType {
+	fn to_u8(self) -> u8 {
+		match self {
+			MessageType::Ping => 1,
+			MessageType::Pong => 2,
+		}
+	}
+
+	fn from_u8(value: u8) -> Option<Self> {
+		match value {
+			1 => Some(MessageType::Ping),
+			2 => Some(MessageType::Pong),
+			_ => None,
+		}
+	}
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Tlv {
+	pub kind: u16,
+	pub value: Vec<u8>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Packet {
+	pub msg_type: MessageType,
+	pub flags: u16,
+	pub session_id: u32,
+	pub seq: u64,
+	pub timestamp: u64,
+	pub tlvs: Vec<Tlv>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum DecodeError {
+	TooShort(usize),
+	BadMagic,
+	UnsupportedVersion(u8),
+	UnknownMessageType(u8),
+	TruncatedTlv,
+}
+
+impl fmt::Display for DecodeError {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		match self {
+			DecodeError::TooShort(len) => write!(f, "packet too short ({} bytes)", len),
+			DecodeError::BadMagic => write!(f, "bad magic"),
+			DecodeError::UnsupportedVersion(v) => write!(f, "unsupported version {}", v),
+			DecodeError::UnknownMessageType(t) => write!(f, "unknown message type {}", t),
+			DecodeError::TruncatedTlv => write!(f, "truncated TLV"),
+		}
+	}
+}
+
+impl std::error::Error for DecodeError {}
+
+impl Packet {
+	pub fn new(msg_type: MessageType, session_id: u32, seq: u64, timestamp: u64) -> Self {
+		Packet {
+			msg_type,
+			flags: 0,
+			session_id,
+			seq,
+			timestamp,
+			tlvs: Vec::new(),
+		}
+	}
+
+	/// Build the reply to a ping, echoing its session id, sequence number and timestamp.
+	pub fn reply_to(ping: &Packet) -> Self {
+		Packet::new(MessageType::Pong, ping.session_id, ping.seq, ping.timestamp)
+	}
+
+	pub fn push_tlv(&mut self, kind: u16, value: Vec<u8>) {
+		self.tlvs.push(Tlv { kind, value });
+	}
+
+	/// Returns the value of the first TLV of the given type.
+	pub fn tlv(&self, kind: u16) -> Option<&[u8]> {
+		self.tlvs.iter().find(|t| t.kind == kind).map(|t| t.value.as_slice())
+	}
+
+	pub fn server_id(&self) -> Option<u32> {
+		self.tlv(TLV_SERVER_ID)
+			.and_then(|v| v.try_into().ok())
+			.map(u32::from_be_bytes)
+	}
+
+	pub fn tlv_u32(&self, kind: u16) -> Option<u32> {
+		self.tlv(kind).and_then(|v| v.try_into().ok()).map(u32::from_be_bytes)
+	}
+
+	pub fn tlv_u64(&self, kind: u16) -> Option<u64> {
+		self.tlv(kind).and_then(|v| v.try_into().ok()).map(u64::from_be_bytes)
+	}
+
+	/// Append a padding TLV so the encoded packet is `size` bytes long. Packets
+	/// already within a TLV header of that size get an empty padding TLV.
+	pub fn pad_to(&mut self, size: usize) {
+		let len = self.encoded_len() + TLV_HEADER_LEN;
+		let padding = (0..size.saturating_sub(len)).map(|i| padding_byte(self.seq, i)).collect();
+		self.push_tlv(TLV_PADDING, padding);
+	}
+
+	pub fn encoded_len(&self) -> usize {
+		HEADER_LEN + self.tlvs.iter().map(|t| TLV_HEADER_LEN + t.value.len()).sum::<usize>()
+	}
+
+	/// Value of a single-byte TLV.
+	pub fn tlv_u8(&self, kind: u16) -> Option<u8> {
+		match self.tlv(kind) {
+			Some(&[value]) => Some(value),
+			_ => None,
+		}
+	}
+
+	pub fn encode(&self) -> Vec<u8> {
+		let mut buf = Vec::with_capacity(self.encoded_len());
+
+		buf.extend_from_slice(&MAGIC);
+		buf.push(VERSION);
+		buf.push(self.msg_type.to_u8());
+		buf.extend_from_slice(&self.flags.to_be_bytes());
+		buf.extend_from_slice(&self.session_id.to_be_bytes());
+		buf.extend_from_slice(&self.seq.to_be_bytes());
+		buf.extend_from_slice(&self.timestamp.to_be_bytes());
+		buf.extend_from_slice(&0u32.to_be_bytes());
+
+		for tlv in &self.tlvs {
+			// Values longer than a u16 can describe are cut off rather than corrupting the stream
+			let len = tlv.value.len().min(u16::MAX as usize);
+			buf.extend_from_slice(&tlv.kind.to_be_bytes());
+			buf.extend_from_slice(&(len as u16).to_be_bytes());
+			buf.extend_from_slice(&tlv.value[..len]);
+		}
+
+		buf
+	}
+
+	pub fn decode(buf: &[u8]) -> Result<Self, DecodeError> {
+		Self::decode_inner(buf, false)
+	}
+
+	/// Decode a datagram that was cut short by the receive buffer. The header
+	/// must be complete; a trailing TLV that doesn't fit is dropped.
+	pub fn decode_truncated(buf: &[u8]) -> Result<Self, DecodeError> {
+		Self::decode_inner(buf, true)
+	}
+
+	fn decode_inner(buf: &[u8], truncated: bool) -> Result<Self, DecodeError> {
+		if buf.len() < HEADER_LEN {
+			return Err(DecodeError::TooShort(buf.len()));
+		}
+		if buf[0..4] != MAGIC {
+			return Err(DecodeError::BadMagic);
+		}
+		if buf[4] != VERSION {
+			return Err(DecodeError::UnsupportedVersion(buf[4]));
+		}
+		let msg_type = MessageType::from_u8(buf[5]).ok_or(DecodeError::UnknownMessageType(buf[5]))?;
+
+		let flags = u16::from_be_bytes([buf[6], buf[7]]);
+		let session_id = u32::from_be_bytes(buf[8..12].try_into().unwrap());
+		let seq = u64::from_be_bytes(buf[12..20].try_into().unwrap());
+		let timestamp = u64::from_be_bytes(buf[20..28].try_into().unwrap());
+
+		let mut tlvs = Vec::new();
+		let mut rest = &buf[HEADER_LEN..];
+		while !rest.is_empty() {
+			if rest.len() < TLV_HEADER_LEN {
+				if truncated {
+					break;
+				}
+				return Err(DecodeError::TruncatedTlv);
+			}
+			let kind = u16::from_be_bytes([rest[0], rest[1]]);
+			let len = u16::from_be_bytes([rest[2], rest[3]]) as usize;
+			rest = &rest[TLV_HEADER_LEN..];
+			if rest.len() < len {
+				if truncated {
+					break;
+				}
+				return Err(DecodeError::TruncatedTlv);
+			}
+			tlvs.push(Tlv { kind, value: rest[..len].to_vec() });
+			rest = &rest[len..];
+		}
+
+		Ok(Packet {
+			msg_type,
+			flags,
+			session_id,
+			seq,
+			timestamp,
+			tlvs,
+		})
+	}
+}
+
+/// Byte `index` of the padding of probe `seq`. The pattern depends on the
+/// sequence number so a receiver can tell corrupted or misplaced padding.
+pub fn padding_byte(seq: u64, index: usize) -> u8 {
+	(seq as u8).wrapping_mul(31).wrapping_add(index as u8)
+}
+
+/// Overwrite the send timestamp of an encoded packet, so it can be taken
+/// right before sending rather than before the packet was built.
+pub fn set_timestamp(buf: &mut [u8], timestamp: u64) {
+	buf[20..28].copy_from_slice(&timestamp.to_be_bytes());
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	fn ping() -> Packet {
+		let mut packet = Packet::new(MessageType::Ping, 0xdead_beef, 42, 1_700_000_000_123_456_789);
+		packet.push_tlv(TLV_HOP_LIMIT, vec![8]);
+		packet.push_tlv(TLV_SERVER_ID, 0x0102_0304u32.to_be_bytes().to_vec());
+		packet
+	}
+
+	#[test]
+	fn round_trip() {
+		let packet = ping();
+		let buf = packet.encode();
+		assert_eq!(buf.len(), HEADER_LEN + 2 * TLV_HEADER_LEN + 1 + 4);
+		assert_eq!(&buf[0..4], b"MCPG");
+		assert_eq!(Packet::decode(&buf), Ok(packet.clone()));
+
+		let decoded = Packet::decode(&buf).unwrap();
+		assert_eq!(decoded.tlv_u8(TLV_HOP_LIMIT), Some(8));
+		assert_eq!(decoded.server_id(), Some(0x0102_0304));
+	}
+
+	#[test]
+	fn reply_echoes_probe() {
+		let mut pong = Packet::reply_to(&ping());
+		pong.flags = FLAG_TRUNCATED;
+		let decoded = Packet::decode(&pong.encode()).unwrap();
+		assert_eq!(decoded.msg_type, MessageType::Pong);
+		assert_eq!(decoded.session_id, 0xdead_beef);
+		assert_eq!(decoded.seq, 42);
+		assert_eq!(decoded.timestamp, 1_700_000_000_123_456_789);
+		assert_eq!(decoded.flags, FLAG_TRUNCATED);
+		assert!(decoded.tlvs.is_empty());
+	}
+
+	#[test]
+	fn unknown_tlvs_are_kept() {
+		let mut packet = ping();
+		packet.push_tlv(0xfff0, vec![1, 2, 3]);
+		let decoded = Packet::decode(&packet.encode()).unwrap();
+		assert_eq!(decoded.tlv(0xfff0), Some(&[1, 2, 3][..]));
+		assert_eq!(decoded.tlvs.len(), 3);
+	}
+
+	#[test]
+	fn padding() {
+		let mut packet = ping();
+		packet.pad_to(200);
+		let buf = packet.encode();
+		assert_eq!(buf.len(), 200);
+		let padding = Packet::decode(&buf).unwrap().tlv(TLV_PADDING).unwrap().to_vec();
+		assert!(padding.iter().enumerate().all(|(i, &b)| b == padding_byte(42, i)));
+
+		// Too small to pad: an empty padding TLV
+		let mut packet = ping();
+		packet.pad_to(HEADER_LEN);
+		assert_eq!(packet.tlv(TLV_PADDING), Some(&[][..]));
+	}
+
+	#[test]
+	fn too_short() {
+		let buf = ping().encode();
+		assert_eq!(Packet::decode(&buf[..HEADER_LEN - 1]), Err(DecodeError::TooShort(HEADER_LEN - 1)));
+		assert_eq!(Packet::decode(&[]), Err(DecodeError::TooShort(0)));
+	}
+
+	#[test]
+	fn bad_magic() {
+		let mut buf = ping().encode();
+		buf[0] = b'X';
+		assert_eq!(Packet::decode(&buf), Err(DecodeError::BadMagic));
+	}
+
+	#[test]
+	fn unsupported_version() {
+		let mut buf = ping().encode();
+		buf[4] = VERSION + 1;
+		assert_eq!(Packet::decode(&buf), Err(DecodeError::UnsupportedVersion(VERSION + 1)));
+	}
+
+	#[test]
+	fn unknown_message_type() {
+		let mut buf = ping().encode();
+		buf[5] = 9;
+		assert_eq!(Packet::decode(&buf), Err(DecodeError::UnknownMessageType(9)));
+	}
+
+	#[test]
+	fn truncated_tlv() {
+		let buf = ping().encode();
+		// Cut inside the last TLV's value, then inside a TLV header
+		let cut_value = &buf[..buf.len() - 1];
+		let cut_header = &buf[..HEADER_LEN + 2];
+		assert_eq!(Packet::decode(cut_value), Err(DecodeError::TruncatedTlv));
+		assert_eq!(Packet::decode(cut_header), Err(DecodeError::TruncatedTlv));
+
+		// Lenient decoding drops the incomplete TLV and keeps the rest
+		let decoded = Packet::decode_truncated(cut_value).unwrap();
+		assert_eq!(decoded.tlv_u8(TLV_HOP_LIMIT), Some(8));
+		assert_eq!(decoded.server_id(), None);
+		assert!(Packet::decode_truncated(cut_header).unwrap().tlvs.is_empty());
+
+		// ...but still needs a complete header
+		assert_eq!(Packet::decode_truncated(&buf[..10]), Err(DecodeError::TooShort(10)));
+	}
+
+	#[test]
+	fn smallest_probe() {
+		let mut probe = Packet::new(MessageType::Ping, 1, 1, 0);
+		probe.push_tlv(TLV_HOP_LIMIT, vec![1]);
+		probe.push_tlv(TLV_TCLASS, vec![0]);
+		probe.pad_to(MIN_PROBE_LEN);
+		assert_eq!(probe.encode().len(), MIN_PROBE_LEN);
+	}
+
+	#[test]
+	fn restamp() {
+		let mut buf = ping().encode();
+		set_timestamp(&mut buf, 1_800_000_000_000_000_001);
+		let decoded = Packet::decode(&buf).unwrap();
+		assert_eq!(decoded.timestamp, 1_800_000_000_000_000_001);
+		assert_eq!(decoded.seq, 42);
+		assert_eq!(decoded.tlv_u8(TLV_HOP_LIMIT), Some(8));
+	}
+}
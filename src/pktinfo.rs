@@ -0,0 +1,294 @@
+//! Receiving datagrams together with the interface, destination address,
+//! hop limit and traffic class they arrived with, and sending replies out of
+//! a chosen interface.
+//!
+//! On Linux this enables `IPV6_RECVPKTINFO` / `IP_PKTINFO`,
+//! `IPV6_RECVHOPLIMIT` / `IP_RECVTTL` and `IPV6_RECVTCLASS` / `IP_RECVTOS`,
+//! reads the ancillary data with
+//! `recvmsg` and passes packet info back with `sendmsg`. Kernel receive
+//! timestamps (`SO_TIMESTAMPNS`) can be enabled as well. Elsewhere it falls
+//! back to `recv_from` / `send_to` and none of this is known.
+
+use std::io;
+use std::net::{IpAddr, SocketAddr, UdpSocket};
+
+#[derive(Debug, Clone, Copy)]
+pub struct RecvInfo {
+	/// Number of bytes placed in the buffer.
+	pub len: usize,
+	/// Full length of the datagram if it didn't fit in the buffer, if known.
+	pub truncated_from: Option<usize>,
+	pub source: SocketAddr,
+	/// Index of the interface the datagram arrived on, if known.
+	pub if_index: Option<u32>,
+	/// Destination address of the datagram (the group for multicast), if known.
+	pub dst: Option<IpAddr>,
+	/// Remaining hop limit (TTL for IPv4) of the datagram, if known.
+	pub hop_limit: Option<u8>,
+	/// Traffic class (TOS for IPv4) of the datagram, if known.
+	pub tclass: Option<u8>,
+	/// Wall-clock time the kernel received the datagram, in nanoseconds since
+	/// the UNIX epoch, if kernel timestamps are enabled.
+	pub timestamp: Option<u64>,
+}
+
+impl RecvInfo {
+	/// Source address to reply from: the destination address if it was
+	/// unicast, otherwise let the kernel pick one on the arrival interface.
+	pub fn reply_source(&self) -> Option<IpAddr> {
+		self.dst.filter(|dst| !dst.is_multicast())
+	}
+}
+
+/// Ask the kernel to timestamp every received datagram.
+pub fn enable_timestamps(socket: &UdpSocket) -> io::Result<()> {
+	#[cfg(target_os = "linux")]
+	{
+		linux::set_int_option(socket, libc::SOL_SOCKET, libc::SO_TIMESTAMPNS, 1)
+	}
+
+	#[cfg(not(target_os = "linux"))]
+	{
+		let _ = socket;
+		Err(io::Error::new(io::ErrorKind::Unsupported, "Kernel timestamps are only supported on Linux"))
+	}
+}
+
+/// Ask the kernel to attach packet info, hop limit and traffic class to every received datagram.
+pub fn enable(socket: &UdpSocket, ipv4: bool) -> io::Result<()> {
+	#[cfg(target_os = "linux")]
+	{
+		let options = if ipv4 {
+			[
+				(libc::IPPROTO_IP, libc::IP_PKTINFO),
+				(libc::IPPROTO_IP, libc::IP_RECVTTL),
+				(libc::IPPROTO_IP, libc::IP_RECVTOS),
+			]
+		} else {
+			[
+				(libc::IPPROTO_IPV6, libc::IPV6_RECVPKTINFO),
+				(libc::IPPROTO_IPV6, libc::IPV6_RECVHOPLIMIT),
+				(libc::IPPROTO_IPV6, libc::IPV6_RECVTCLASS),
+			]
+		};
+		for (level, name) in options {
+			linux::set_int_option(socket, level, name, 1)?;
+		}
+		Ok(())
+	}
+
+	#[cfg(not(target_os = "linux"))]
+	{
+		let _ = (socket, ipv4);
+		Ok(())
+	}
+}
+
+pub fn recv(socket: &UdpSocket, buf: &mut [u8]) -> io::Result<RecvInfo> {
+	#[cfg(target_os = "linux")]
+	{
+		linux::recv(socket, buf)
+	}
+
+	#[cfg(not(target_os = "linux"))]
+	{
+		let (len, source) = socket.recv_from(buf)?;
+		Ok(RecvInfo { len, truncated_from: None, source, if_index: None, dst: None, hop_limit: None, tclass: None, timestamp: None })
+	}
+}
+
+/// Send `buf` to `dest`, out of interface `if_index` and from address `src`
+/// when given.
+pub fn send_to(
+	socket: &UdpSocket,
+	buf: &[u8],
+	dest: SocketAddr,
+	if_index: Option<u32>,
+	src: Option<IpAddr>,
+) -> io::Result<usize> {
+	#[cfg(target_os = "linux")]
+	{
+		if if_index.is_none() && src.is_none() {
+			return socket.send_to(buf, dest);
+		}
+		linux::send_to(socket, buf, dest, if_index.unwrap_or(0), src)
+	}
+
+	#[cfg(not(target_os = "linux"))]
+	{
+		let _ = (if_index, src);
+		socket.send_to(buf, dest)
+	}
+}
+
+#[cfg(target_os = "linux")]
+mod linux {
+	use super::RecvInfo;
+	use socket2::SockAddr;
+	use std::io;
+	use std::mem;
+	use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr, UdpSocket};
+	use std::os::fd::AsRawFd;
+
+	/// Room for a few control messages; pktinfo is the largest we ask for.
+	const CONTROL_LEN: usize = 256;
+
+	pub fn set_int_option(socket: &UdpSocket, level: libc::c_int, name: libc::c_int, value: libc::c_int) -> io::Result<()> {
+		let ret = unsafe {
+			libc::setsockopt(
+				socket.as_raw_fd(),
+				level,
+				name,
+				&value as *const libc::c_int as *const libc::c_void,
+				mem::size_of::<libc::c_int>() as libc::socklen_t,
+			)
+		};
+		if ret != 0 {
+			return Err(io::Error::last_os_error());
+		}
+		Ok(())
+	}
+
+	pub fn recv(socket: &UdpSocket, buf: &mut [u8]) -> io::Result<RecvInfo> {
+		let mut storage: libc::sockaddr_storage = unsafe { mem::zeroed() };
+		let mut iov = libc::iovec {
+			iov_base: buf.as_mut_ptr() as *mut libc::c_void,
+			iov_len: buf.len(),
+		};
+		// u64 elements keep the buffer aligned for cmsghdr
+		let mut control = [0u64; CONTROL_LEN / 8];
+
+		let mut msg: libc::msghdr = unsafe { mem::zeroed() };
+		msg.msg_name = &mut storage as *mut _ as *mut libc::c_void;
+		msg.msg_namelen = mem::size_of::<libc::sockaddr_storage>() as libc::socklen_t;
+		msg.msg_iov = &mut iov;
+		msg.msg_iovlen = 1;
+		msg.msg_control = control.as_mut_ptr() as *mut libc::c_void;
+		msg.msg_controllen = CONTROL_LEN;
+
+		// With MSG_TRUNC the full datagram length is returned even if it was cut short
+		let len = unsafe { libc::recvmsg(socket.as_raw_fd(), &mut msg, libc::MSG_TRUNC) };
+		if len < 0 {
+			return Err(io::Error::last_os_error());
+		}
+		let len = len as usize;
+		let truncated_from = (msg.msg_flags & libc::MSG_TRUNC != 0).then_some(len);
+
+		let source = unsafe { SockAddr::new(storage, msg.msg_namelen) }
+			.as_socket()
+			.ok_or_else(|| io::Error::other("Invalid source address"))?;
+
+		let mut if_index = None;
+		let mut dst = None;
+		let mut hop_limit = None;
+		let mut tclass = None;
+		let mut timestamp = None;
+		let mut cmsg = unsafe { libc::CMSG_FIRSTHDR(&msg) };
+		while !cmsg.is_null() {
+			let hdr = unsafe { &*cmsg };
+			let data = unsafe { libc::CMSG_DATA(cmsg) };
+			match (hdr.cmsg_level, hdr.cmsg_type) {
+				(libc::IPPROTO_IPV6, libc::IPV6_PKTINFO) => {
+					let info = unsafe { std::ptr::read_unaligned(data as *const libc::in6_pktinfo) };
+					if_index = Some(info.ipi6_ifindex);
+					dst = Some(IpAddr::V6(Ipv6Addr::from(info.ipi6_addr.s6_addr)));
+				}
+				(libc::IPPROTO_IP, libc::IP_PKTINFO) => {
+					let info = unsafe { std::ptr::read_unaligned(data as *const libc::in_pktinfo) };
+					if_index = Some(info.ipi_ifindex as u32);
+					dst = Some(IpAddr::V4(Ipv4Addr::from(u32::from_be(info.ipi_addr.s_addr))));
+				}
+				(libc::IPPROTO_IPV6, libc::IPV6_HOPLIMIT) => {
+					let value = unsafe { std::ptr::read_unaligned(data as *const libc::c_int) };
+					hop_limit = u8::try_from(value).ok();
+				}
+				(libc::IPPROTO_IP, libc::IP_TTL) => {
+					// Linux passes the TTL as an int here, unlike the byte some BSDs use
+					let value = unsafe { std::ptr::read_unaligned(data as *const libc::c_int) };
+					hop_limit = u8::try_from(value).ok();
+				}
+				(libc::IPPROTO_IPV6, libc::IPV6_TCLASS) => {
+					let value = unsafe { std::ptr::read_unaligned(data as *const libc::c_int) };
+					tclass = u8::try_from(value).ok();
+				}
+				(libc::IPPROTO_IP, libc::IP_TOS) => {
+					// A single byte, unlike IP_TTL
+					tclass = Some(unsafe { *data });
+				}
+				(libc::SOL_SOCKET, libc::SCM_TIMESTAMPNS) => {
+					let ts = unsafe { std::ptr::read_unaligned(data as *const libc::timespec) };
+					timestamp = Some(ts.tv_sec as u64 * 1_000_000_000 + ts.tv_nsec as u64);
+				}
+				_ => {}
+			}
+			cmsg = unsafe { libc::CMSG_NXTHDR(&msg, cmsg) };
+		}
+
+		Ok(RecvInfo {
+			len: len.min(buf.len()),
+			truncated_from,
+			source,
+			if_index,
+			dst,
+			hop_limit,
+			tclass,
+			timestamp,
+		})
+	}
+
+	pub fn send_to(
+		socket: &UdpSocket,
+		buf: &[u8],
+		dest: SocketAddr,
+		if_index: u32,
+		src: Option<IpAddr>,
+	) -> io::Result<usize> {
+		let dest = SockAddr::from(dest);
+		let mut iov = libc::iovec {
+			iov_base: buf.as_ptr() as *mut libc::c_void,
+			iov_len: buf.len(),
+		};
+		let mut control = [0u64; CONTROL_LEN / 8];
+
+		let mut msg: libc::msghdr = unsafe { mem::zeroed() };
+		msg.msg_name = dest.as_ptr() as *mut libc::c_void;
+		msg.msg_namelen = dest.len();
+		msg.msg_iov = &mut iov;
+		msg.msg_iovlen = 1;
+		msg.msg_control = control.as_mut_ptr() as *mut libc::c_void;
+
+		unsafe {
+			if dest.is_ipv6() {
+				let mut info: libc::in6_pktinfo = mem::zeroed();
+				info.ipi6_ifindex = if_index;
+				if let Some(IpAddr::V6(src)) = src {
+					info.ipi6_addr.s6_addr = src.octets();
+				}
+				msg.msg_controllen = libc::CMSG_SPACE(mem::size_of_val(&info) as u32) as usize;
+				let cmsg = libc::CMSG_FIRSTHDR(&msg);
+				(*cmsg).cmsg_level = libc::IPPROTO_IPV6;
+				(*cmsg).cmsg_type = libc::IPV6_PKTINFO;
+				(*cmsg).cmsg_len = libc::CMSG_LEN(mem::size_of_val(&info) as u32) as usize;
+				std::ptr::write_unaligned(libc::CMSG_DATA(cmsg) as *mut libc::in6_pktinfo, info);
+			} else {
+				let mut info: libc::in_pktinfo = mem::zeroed();
+				info.ipi_ifindex = if_index as libc::c_int;
+				if let Some(IpAddr::V4(src)) = src {
+					info.ipi_spec_dst.s_addr = u32::from(src).to_be();
+				}
+				msg.msg_controllen = libc::CMSG_SPACE(mem::size_of_val(&info) as u32) as usize;
+				let cmsg = libc::CMSG_FIRSTHDR(&msg);
+				(*cmsg).cmsg_level = libc::IPPROTO_IP;
+				(*cmsg).cmsg_type = libc::IP_PKTINFO;
+				(*cmsg).cmsg_len = libc::CMSG_LEN(mem::size_of_val(&info) as u32) as usize;
+				std::ptr::write_unaligned(libc::CMSG_DATA(cmsg) as *mut libc::in_pktinfo, info);
+			}
+		}
+
+		let len = unsafe { libc::sendmsg(socket.as_raw_fd(), &msg, 0) };
+		if len < 0 {
+			return Err(io::Error::last_os_error());
+		}
+		Ok(len as usize)
+	}
+}
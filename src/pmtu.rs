@@ -0,0 +1,58 @@
+//! Don't-fragment handling for path MTU probing.
+//!
+//! On Linux this sets `IPV6_DONTFRAG` / `IP_MTU_DISCOVER` so oversized probes
+//! fail with `EMSGSIZE` instead of being fragmented. Elsewhere probes may
+//! still be fragmented by the sending host.
+
+use socket2::Socket;
+use std::io;
+use std::net::IpAddr;
+
+/// Largest UDP payload that fits in an IPv4 datagram.
+pub const MAX_UDP_PAYLOAD: usize = 65507;
+
+/// Stop the kernel from fragmenting datagrams sent on `socket`.
+pub fn set_dont_fragment(socket: &Socket, family: IpAddr) -> io::Result<()> {
+	#[cfg(target_os = "linux")]
+	{
+		use std::os::fd::AsRawFd;
+
+		let (level, name, value) = match family {
+			IpAddr::V4(_) => (libc::IPPROTO_IP, libc::IP_MTU_DISCOVER, libc::IP_PMTUDISC_DO),
+			IpAddr::V6(_) => (libc::IPPROTO_IPV6, libc::IPV6_DONTFRAG, 1),
+		};
+		let ret = unsafe {
+			libc::setsockopt(
+				socket.as_raw_fd(),
+				level,
+				name,
+				&value as *const libc::c_int as *const libc::c_void,
+				std::mem::size_of::<libc::c_int>() as libc::socklen_t,
+			)
+		};
+		if ret != 0 {
+			return Err(io::Error::last_os_error());
+		}
+		Ok(())
+	}
+
+	#[cfg(not(target_os = "linux"))]
+	{
+		let _ = (socket, family);
+		Err(io::Error::new(io::ErrorKind::Unsupported, "Setting don't-fragment is only supported on Linux"))
+	}
+}
+
+/// Whether a send failed because the datagram would have needed fragmentation.
+pub fn is_too_big(error: &io::Error) -> bool {
+	#[cfg(unix)]
+	{
+		error.raw_os_error() == Some(libc::EMSGSIZE)
+	}
+
+	#[cfg(not(unix))]
+	{
+		let _ = error;
+		false
+	}
+}
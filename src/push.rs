@@ -0,0 +1,281 @@
+//! Pushing periodic statistics to InfluxDB or Graphite (`--push`).
+
+use crate::responders::{Responder, ResponderKey, ResponderTable};
+use crate::json::report;
+use clap::ValueEnum;
+use std::collections::BTreeSet;
+use std::fmt::Write as _;
+use std::io::{self, Write};
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr, TcpStream, ToSocketAddrs, UdpSocket};
+use std::sync::mpsc::{self, SyncSender};
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
+
+/// How long to wait for a TCP endpoint before giving up on a push.
+const TCP_TIMEOUT: Duration = Duration::from_secs(2);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum PushFormat {
+	/// InfluxDB line protocol
+	Influx,
+	/// Graphite plaintext protocol
+	Graphite,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Transport {
+	Tcp,
+	Udp,
+}
+
+/// Where to push to: `tcp://host:port` or `udp://host:port`.
+#[derive(Debug, Clone)]
+pub struct Endpoint {
+	transport: Transport,
+	addr: SocketAddr,
+}
+
+impl std::fmt::Display for Endpoint {
+	fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+		let scheme = match self.transport {
+			Transport::Tcp => "tcp",
+			Transport::Udp => "udp",
+		};
+		write!(f, "{}://{}", scheme, self.addr)
+	}
+}
+
+pub fn parse_endpoint(value: &str) -> Result<Endpoint, String> {
+	let (transport, rest) = if let Some(rest) = value.strip_prefix("tcp://") {
+		(Transport::Tcp, rest)
+	} else if let Some(rest) = value.strip_prefix("udp://") {
+		(Transport::Udp, rest)
+	} else {
+		return Err(format!("invalid endpoint '{}' (use tcp://HOST:PORT or udp://HOST:PORT)", value));
+	};
+	let addr = rest
+		.to_socket_addrs()
+		.map_err(|e| format!("invalid endpoint '{}': {}", value, e))?
+		.next()
+		.ok_or_else(|| format!("'{}' did not resolve to an address", rest))?;
+	Ok(Endpoint { transport, addr })
+}
+
+/// Pushes statistics from a background thread, so a slow or unreachable
+/// endpoint doesn't hold up the probes.
+pub struct Pusher {
+	format: PushFormat,
+	batches: SyncSender<Vec<String>>,
+	thread: JoinHandle<()>,
+}
+
+impl Pusher {
+	pub fn spawn(endpoint: Endpoint, format: PushFormat) -> Self {
+		// Room for one batch besides the one being sent; see `push`
+		let (batches, rx) = mpsc::sync_channel::<Vec<String>>(1);
+		let thread = thread::spawn(move || {
+			let mut connection = Connection::new(endpoint);
+			for batch in rx {
+				if let Err(e) = connection.send_batch(&batch) {
+					report!("Push error: {}", e);
+				}
+			}
+		});
+		Pusher { format, batches, thread }
+	}
+
+	/// Queue the statistics of every responder, stamped with `time` (ns since
+	/// the UNIX epoch). Skipped if the endpoint is still busy with earlier ones.
+	pub fn push(&self, group: &str, table: &ResponderTable, last_seq: u64, time: u64) {
+		let _ = self.batches.try_send(self.render(group, table, last_seq, time));
+	}
+
+	/// Push the final statistics and wait until everything queued was sent.
+	pub fn finish(self, group: &str, table: &ResponderTable, last_seq: u64, time: u64) {
+		let _ = self.batches.send(self.render(group, table, last_seq, time));
+		drop(self.batches);
+		let _ = self.thread.join();
+	}
+
+	/// The statistics of each responder, in one piece each.
+	fn render(&self, group: &str, table: &ResponderTable, last_seq: u64, time: u64) -> Vec<String> {
+		table
+			.responders
+			.iter()
+			.map(|(key, responder)| {
+				let fields = fields(responder, last_seq, &table.unsent);
+				match self.format {
+					PushFormat::Influx => influx_line(group, key, &fields, time),
+					PushFormat::Graphite => graphite_lines(group, key, &fields, time),
+				}
+			})
+			.collect()
+	}
+}
+
+/// Sends statistics to one endpoint, reconnecting over TCP as needed.
+struct Connection {
+	endpoint: Endpoint,
+	tcp: Option<TcpStream>,
+	udp: Option<UdpSocket>,
+}
+
+impl Connection {
+	fn new(endpoint: Endpoint) -> Self {
+		Connection { endpoint, tcp: None, udp: None }
+	}
+
+	fn send_batch(&mut self, batch: &[String]) -> io::Result<()> {
+		for lines in batch {
+			// One responder per datagram keeps UDP pushes well below the MTU
+			if let Err(e) = self.send(lines.as_bytes()) {
+				self.tcp = None;
+				return Err(e);
+			}
+		}
+		Ok(())
+	}
+
+	fn send(&mut self, data: &[u8]) -> io::Result<()> {
+		match self.endpoint.transport {
+			Transport::Tcp => {
+				if self.tcp.is_none() {
+					let stream = TcpStream::connect_timeout(&self.endpoint.addr, TCP_TIMEOUT)?;
+					stream.set_write_timeout(Some(TCP_TIMEOUT))?;
+					self.tcp = Some(stream);
+				}
+				self.tcp.as_mut().unwrap().write_all(data)
+			}
+			Transport::Udp => {
+				if self.udp.is_none() {
+					let unspecified: IpAddr = match self.endpoint.addr {
+						SocketAddr::V4(_) => Ipv4Addr::UNSPECIFIED.into(),
+						SocketAddr::V6(_) => Ipv6Addr::UNSPECIFIED.into(),
+					};
+					let bind = SocketAddr::new(unspecified, 0);
+					self.udp = Some(UdpSocket::bind(bind)?);
+				}
+				self.udp.as_ref().unwrap().send_to(data, self.endpoint.addr).map(|_| ())
+			}
+		}
+	}
+}
+
+/// Statistics pushed per responder; the RTT fields only once it has answered.
+fn fields(responder: &Responder, last_seq: u64, unsent: &BTreeSet<u64>) -> Vec<(&'static str, f64)> {
+	let mut fields = vec![
+		("sent", responder.sent(last_seq, unsent) as f64),
+		("received", responder.received as f64),
+		("loss_pct", responder.loss_percent(last_seq, unsent)),
+		("late", responder.late as f64),
+		("duplicates", responder.duplicates as f64),
+		("out_of_order", responder.out_of_order as f64),
+		("corrupted", responder.corrupted as f64),
+		("truncated", responder.truncated as f64),
+		("jitter_ms", responder.jitter.value()),
+	];
+	let rtt = &responder.rtt;
+	if rtt.count() > 0 {
+		fields.extend([
+			("rtt_min_ms", rtt.min()),
+			("rtt_avg_ms", rtt.avg()),
+			("rtt_max_ms", rtt.max()),
+			("rtt_mdev_ms", rtt.mdev()),
+			("rtt_p50_ms", rtt.percentile(50.0)),
+			("rtt_p90_ms", rtt.percentile(90.0)),
+			("rtt_p99_ms", rtt.percentile(99.0)),
+		]);
+	}
+	fields
+}
+
+/// `multicast_ping,group=...,responder=...,server_id=... sent=10,... <ns>`
+fn influx_line(group: &str, key: &ResponderKey, fields: &[(&str, f64)], time: u64) -> String {
+	// Tag values escape commas, spaces and equals signs
+	let tag = |value: &str| value.replace(',', "\\,").replace(' ', "\\ ").replace('=', "\\=");
+	let mut line = format!("multicast_ping,group={},responder={}", tag(group), tag(&key.addr.to_string()));
+	if let Some(id) = key.server_id {
+		let _ = write!(line, ",server_id={:08x}", id);
+	}
+	for (i, (name, value)) in fields.iter().enumerate() {
+		let _ = write!(line, "{}{}={}", if i == 0 { ' ' } else { ',' }, name, value);
+	}
+	let _ = writeln!(line, " {}", time);
+	line
+}
+
+/// `multicast_ping.<group>.<responder>.<field> <value> <seconds>`, one line per field.
+fn graphite_lines(group: &str, key: &ResponderKey, fields: &[(&str, f64)], time: u64) -> String {
+	// Dots separate path components, so they and other punctuation become underscores
+	let component = |value: &str| value.replace(|c: char| !c.is_ascii_alphanumeric() && c != '-', "_");
+	let mut responder = component(&key.addr.to_string());
+	if let Some(id) = key.server_id {
+		let _ = write!(responder, "_{:08x}", id);
+	}
+	let mut lines = String::new();
+	for (name, value) in fields {
+		let _ = writeln!(lines, "multicast_ping.{}.{}.{} {} {}",
+						 component(group), responder, name, value, time / 1_000_000_000);
+	}
+	lines
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	fn key(server_id: Option<u32>) -> ResponderKey {
+		ResponderKey { addr: "[fe80::1%4]:9999".parse().unwrap(), server_id }
+	}
+
+	const FIELDS: [(&str, f64); 3] = [("sent", 10.0), ("loss_pct", 12.5), ("rtt_avg_ms", 0.25)];
+	const TIME: u64 = 1_700_000_000_123_456_789;
+
+	#[test]
+	fn endpoints() {
+		let endpoint = parse_endpoint("tcp://127.0.0.1:2003").unwrap();
+		assert_eq!((endpoint.transport, endpoint.addr), (Transport::Tcp, "127.0.0.1:2003".parse().unwrap()));
+		let endpoint = parse_endpoint("udp://[::1]:8089").unwrap();
+		assert_eq!((endpoint.transport, endpoint.addr), (Transport::Udp, "[::1]:8089".parse().unwrap()));
+		assert_eq!(endpoint.to_string(), "udp://[::1]:8089");
+		assert!(parse_endpoint("http://127.0.0.1:8086").is_err());
+		assert!(parse_endpoint("tcp://127.0.0.1").is_err());
+	}
+
+	#[test]
+	fn influx() {
+		assert_eq!(
+			influx_line("ff15::1234", &key(Some(0x9241_e71c)), &FIELDS, TIME),
+			"multicast_ping,group=ff15::1234,responder=[fe80::1%4]:9999,server_id=9241e71c \
+			 sent=10,loss_pct=12.5,rtt_avg_ms=0.25 1700000000123456789\n"
+		);
+		assert_eq!(
+			influx_line("group a,b=c", &key(None), &FIELDS[..1], TIME),
+			"multicast_ping,group=group\\ a\\,b\\=c,responder=[fe80::1%4]:9999 sent=10 1700000000123456789\n"
+		);
+	}
+
+	#[test]
+	fn graphite() {
+		assert_eq!(
+			graphite_lines("ff15::1234", &key(Some(0x9241_e71c)), &FIELDS, TIME),
+			"multicast_ping.ff15__1234._fe80__1_4__9999_9241e71c.sent 10 1700000000\n\
+			 multicast_ping.ff15__1234._fe80__1_4__9999_9241e71c.loss_pct 12.5 1700000000\n\
+			 multicast_ping.ff15__1234._fe80__1_4__9999_9241e71c.rtt_avg_ms 0.25 1700000000\n"
+		);
+		assert_eq!(
+			graphite_lines("239.1.2.3", &key(None), &FIELDS[..1], TIME),
+			"multicast_ping.239_1_2_3._fe80__1_4__9999.sent 10 1700000000\n"
+		);
+	}
+
+	#[test]
+	fn responder_fields() {
+		let mut table = ResponderTable::new();
+		table.record_reply(key(None), 1, 0.5, false);
+		let names: Vec<&str> = fields(&table.responders[&key(None)], 2, &table.unsent).iter().map(|f| f.0).collect();
+		assert_eq!(names[..3], ["sent", "received", "loss_pct"]);
+		assert!(names.contains(&"rtt_p99_ms"));
+		assert_eq!(fields(&table.responders[&key(None)], 2, &table.unsent)[0].1, 2.0);
+	}
+}
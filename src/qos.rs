@@ -0,0 +1,77 @@
+//! DSCP / traffic class helpers.
+
+use socket2::Socket;
+use std::io;
+use std::net::IpAddr;
+
+/// DSCP part of a traffic class / TOS byte, dropping the two ECN bits.
+pub fn dscp(tclass: u8) -> u8 {
+	tclass >> 2
+}
+
+/// Conventional name of a DSCP value (RFC 2474, 2597, 3246, 5865), or its number.
+pub fn dscp_name(dscp: u8) -> String {
+	match dscp {
+		0 => "CS0".to_string(),
+		46 => "EF".to_string(),
+		44 => "VA".to_string(),
+		d if d % 8 == 0 && d <= 56 => format!("CS{}", d / 8),
+		d if (10..=38).contains(&d) && d % 2 == 0 && (d / 2) % 4 != 0 => {
+			format!("AF{}{}", d / 8, (d % 8) / 2)
+		}
+		d => d.to_string(),
+	}
+}
+
+/// Set the DSCP of every packet sent on `socket`.
+pub fn set_dscp(socket: &Socket, family: IpAddr, dscp: u8) -> io::Result<()> {
+	let tclass = u32::from(dscp) << 2;
+	match family {
+		IpAddr::V4(_) => socket.set_tos(tclass),
+		#[cfg(unix)]
+		IpAddr::V6(_) => socket.set_tclass_v6(tclass),
+		#[cfg(not(unix))]
+		IpAddr::V6(_) => Err(io::Error::new(
+			io::ErrorKind::Unsupported,
+			"Setting the IPv6 traffic class is not supported on this platform",
+		)),
+	}
+}
+
+/// "EF → CS0" if the DSCP of a packet changed between sending and receipt.
+pub fn remarking(sent: Option<u8>, received: Option<u8>) -> Option<String> {
+	match (sent, received) {
+		(Some(sent), Some(received)) if dscp(sent) != dscp(received) => {
+			Some(format!("{} → {}", dscp_name(dscp(sent)), dscp_name(dscp(received))))
+		}
+		_ => None,
+	}
+}
+
+/// "EF" if the DSCP a packet was sent with survived, "EF → CS0 (re-marked)" if not.
+pub fn describe(sent: Option<u8>, received: Option<u8>) -> Option<String> {
+	match remarking(sent, received) {
+		Some(remarked) => Some(format!("{} (re-marked)", remarked)),
+		None => received.map(|r| dscp_name(dscp(r))),
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn names() {
+		let names: Vec<String> = [0, 8, 10, 14, 18, 26, 34, 38, 44, 46, 48, 56, 1, 63].map(dscp_name).into();
+		assert_eq!(names, ["CS0", "CS1", "AF11", "AF13", "AF21", "AF31", "AF41", "AF43", "VA", "EF", "CS6", "CS7", "1", "63"]);
+	}
+
+	#[test]
+	fn remarked() {
+		// Traffic class bytes, ECN bits set on receipt
+		assert_eq!(remarking(Some(46 << 2), Some(46 << 2 | 1)), None);
+		assert_eq!(describe(Some(46 << 2), Some(46 << 2 | 1)).as_deref(), Some("EF"));
+		assert_eq!(describe(Some(46 << 2), Some(0)).as_deref(), Some("EF → CS0 (re-marked)"));
+		assert_eq!(describe(Some(46 << 2), None), None);
+	}
+}
@@ -0,0 +1,522 @@
+//! Per-responder bookkeeping for the client.
+
+use crate::clock::Exchange;
+use crate::integrity::Verdict;
+use crate::json::Object;
+use crate::metrics;
+use crate::stats::{Histogram, Jitter, RttStats};
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt;
+use std::net::SocketAddr;
+
+/// A responder is identified by the address its replies come from and, for
+/// servers that send one, the id they stamp into every reply.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub struct ResponderKey {
+	pub addr: SocketAddr,
+	pub server_id: Option<u32>,
+}
+
+impl ResponderKey {
+	/// Add `responder` and `server_id` fields to `object`.
+	pub fn json_fields(&self, object: Object) -> Object {
+		object
+			.field("responder", self.addr.to_string())
+			.field("server_id", self.server_id.map(|id| format!("{:08x}", id)))
+	}
+}
+
+impl fmt::Display for ResponderKey {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		match self.server_id {
+			Some(id) => write!(f, "{} ({:08x})", self.addr, id),
+			None => write!(f, "{}", self.addr),
+		}
+	}
+}
+
+/// Changes in a responder's reachability worth alerting on.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ResponderEvent {
+	/// First reply from this responder
+	New,
+	/// The responder missed this many consecutive probes
+	Lost { missed: u64 },
+	/// A lost responder answered again after missing this many probes
+	Recovered { missed: u64 },
+}
+
+impl ResponderEvent {
+	/// Event name in JSON output.
+	pub fn name(&self) -> &'static str {
+		match self {
+			ResponderEvent::New => "responder_new",
+			ResponderEvent::Lost { .. } => "responder_lost",
+			ResponderEvent::Recovered { .. } => "responder_recovered",
+		}
+	}
+}
+
+impl fmt::Display for ResponderEvent {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		match self {
+			ResponderEvent::New => write!(f, "new responder"),
+			ResponderEvent::Lost { missed } => write!(f, "responder lost ({} probes missed)", missed),
+			ResponderEvent::Recovered { missed } => {
+				write!(f, "responder recovered (after {} missed probes)", missed)
+			}
+		}
+	}
+}
+
+/// Number of recent sequence numbers remembered per responder to spot duplicates.
+const DUPLICATE_WINDOW: u64 = 4096;
+
+/// How a reply arrived relative to the others from the same responder.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ReplyClass {
+	OnTime,
+	/// Arrived after the late threshold
+	Late,
+	/// A probe this responder already answered
+	Duplicate,
+	/// Arrived after the reply to a later probe
+	OutOfOrder,
+}
+
+impl ReplyClass {
+	/// Name in JSON output.
+	pub fn name(&self) -> &'static str {
+		match self {
+			ReplyClass::OnTime => "on_time",
+			ReplyClass::Late => "late",
+			ReplyClass::Duplicate => "duplicate",
+			ReplyClass::OutOfOrder => "out_of_order",
+		}
+	}
+}
+
+impl fmt::Display for ReplyClass {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		match self {
+			ReplyClass::OnTime => write!(f, "on time"),
+			ReplyClass::Late => write!(f, "late"),
+			ReplyClass::Duplicate => write!(f, "duplicate"),
+			ReplyClass::OutOfOrder => write!(f, "out of order"),
+		}
+	}
+}
+
+#[derive(Debug, Clone)]
+pub struct Responder {
+	/// Sequence number of the first probe this responder answered.
+	/// Probes sent before it was first heard from don't count as lost.
+	pub first_seq: u64,
+	/// Highest sequence number answered so far.
+	pub last_seq: u64,
+	/// Distinct probes answered; duplicates are not counted.
+	pub received: u64,
+	pub late: u64,
+	pub duplicates: u64,
+	pub out_of_order: u64,
+	/// Recently answered sequence numbers, within `DUPLICATE_WINDOW` of `last_seq`.
+	seen: BTreeSet<u64>,
+	/// Replies whose echoed payload didn't match what was sent.
+	pub corrupted: u64,
+	/// Replies to probes, or replies themselves, cut short by a receive buffer.
+	pub truncated: u64,
+	pub rtt: RttStats,
+	pub rtt_histogram: Histogram,
+	/// Interarrival jitter of the round-trip times, in arrival order.
+	pub jitter: Jitter,
+	/// One-way delays of probes and replies; they include the clock offset.
+	pub forward: RttStats,
+	pub reverse: RttStats,
+	/// The exchange with the smallest delay, whose offset estimate is the most accurate.
+	pub best_exchange: Option<Exchange>,
+	pub last_rtt: Option<f64>,
+	/// When the last reply arrived, in `Clock` nanoseconds.
+	pub last_seen: Option<u64>,
+	/// Interface the last reply arrived on.
+	pub interface: Option<String>,
+	/// Set once the responder has missed enough probes to be reported lost.
+	pub lost: bool,
+	/// Lowest probe hop limit this responder has answered.
+	pub min_hop_limit: Option<u8>,
+	/// Largest probe, in bytes, this responder received in full.
+	pub max_size: Option<usize>,
+}
+
+impl Responder {
+	fn new(first_seq: u64) -> Self {
+		Responder {
+			first_seq,
+			last_seq: first_seq,
+			received: 0,
+			late: 0,
+			duplicates: 0,
+			out_of_order: 0,
+			seen: BTreeSet::new(),
+			corrupted: 0,
+			truncated: 0,
+			rtt: RttStats::default(),
+			rtt_histogram: Histogram::default(),
+			jitter: Jitter::default(),
+			forward: RttStats::default(),
+			reverse: RttStats::default(),
+			best_exchange: None,
+			last_rtt: None,
+			last_seen: None,
+			interface: None,
+			lost: false,
+			min_hop_limit: None,
+			max_size: None,
+		}
+	}
+
+	/// Number of probes sent since this responder was first seen, given the
+	/// sequence number of the latest probe that has had time to be answered.
+	/// Probes that were answered early always count; probes in `unsent`
+	/// never left the host and don't.
+	pub fn sent(&self, last_seq: u64, unsent: &BTreeSet<u64>) -> u64 {
+		let last_seq = last_seq.max(self.last_seq);
+		let unsent = unsent.range(self.first_seq..=last_seq).count() as u64;
+		last_seq - self.first_seq + 1 - unsent
+	}
+
+	pub fn loss_percent(&self, last_seq: u64, unsent: &BTreeSet<u64>) -> f64 {
+		let sent = self.sent(last_seq, unsent);
+		let received = self.received.min(sent);
+		(sent - received) as f64 / sent as f64 * 100.0
+	}
+
+	/// Whether probe `seq` was answered, as far as the duplicate window reaches back.
+	pub fn answered(&self, seq: u64) -> bool {
+		self.seen.contains(&seq)
+	}
+
+	pub fn to_json(&self, key: &ResponderKey, last_seq: u64, unsent: &BTreeSet<u64>) -> Object {
+		let object = key
+			.json_fields(Object::new())
+			.field("sent", self.sent(last_seq, unsent))
+			.field("received", self.received)
+			.field("loss_pct", self.loss_percent(last_seq, unsent))
+			.field("late", self.late)
+			.field("duplicates", self.duplicates)
+			.field("out_of_order", self.out_of_order)
+			.field("corrupted", self.corrupted)
+			.field("truncated", self.truncated)
+			.field("lost", self.lost)
+			.field("jitter_ms", self.jitter.value());
+		self.rtt
+			.json_fields(object, "rtt")
+			.field("forward_avg_ms", (self.forward.count() > 0).then(|| self.forward.avg()))
+			.field("reverse_avg_ms", (self.reverse.count() > 0).then(|| self.reverse.avg()))
+			.field("clock_offset_ms", self.best_exchange.map(|e| e.offset))
+			.field("min_hop_limit", self.min_hop_limit)
+			.field("max_size", self.max_size)
+	}
+}
+
+#[derive(Debug, Default)]
+pub struct ResponderTable {
+	/// RTT statistics across all responders.
+	pub overall: RttStats,
+	pub responders: BTreeMap<ResponderKey, Responder>,
+	/// Sequence numbers of probes that failed to send, e.g. because they
+	/// needed fragmentation; they count neither as sent nor as lost.
+	pub unsent: BTreeSet<u64>,
+}
+
+impl ResponderTable {
+	pub fn new() -> Self {
+		Self::default()
+	}
+
+	/// Record a reply and classify it, also returning an event if this is a
+	/// new or recovered responder. Duplicates don't count towards the replies
+	/// received or the RTT statistics.
+	pub fn record_reply(
+		&mut self,
+		key: ResponderKey,
+		seq: u64,
+		rtt_ms: f64,
+		late: bool,
+	) -> (Option<ResponderEvent>, ReplyClass) {
+		let mut event = None;
+		let responder = self.responders.entry(key).or_insert_with(|| {
+			event = Some(ResponderEvent::New);
+			Responder::new(seq)
+		});
+
+		if !responder.seen.insert(seq) {
+			responder.duplicates += 1;
+			return (event, ReplyClass::Duplicate);
+		}
+		let class = if late {
+			responder.late += 1;
+			ReplyClass::Late
+		} else if seq < responder.last_seq {
+			responder.out_of_order += 1;
+			ReplyClass::OutOfOrder
+		} else {
+			ReplyClass::OnTime
+		};
+
+		// Only a reply newer than any seen before shows the responder is back;
+		// a stale one may have been in flight since before it was lost
+		if responder.lost && seq > responder.last_seq {
+			responder.lost = false;
+			let unsent = self.unsent.range(responder.last_seq + 1..seq).count() as u64;
+			let missed = seq.saturating_sub(responder.last_seq + 1) - unsent;
+			event = Some(ResponderEvent::Recovered { missed });
+		}
+
+		responder.first_seq = responder.first_seq.min(seq);
+		responder.last_seq = responder.last_seq.max(seq);
+		let oldest = responder.last_seq.saturating_sub(DUPLICATE_WINDOW);
+		responder.seen = responder.seen.split_off(&oldest);
+		responder.received += 1;
+		responder.rtt.add(rtt_ms);
+		responder.rtt_histogram.add(rtt_ms);
+		responder.last_rtt = Some(rtt_ms);
+		responder.jitter.add(rtt_ms);
+		self.overall.add(rtt_ms);
+		(event, class)
+	}
+
+	/// Record that a probe sent with `hop_limit` reached the responder,
+	/// returning whether that's the lowest hop limit seen so far.
+	pub fn record_reach(&mut self, key: ResponderKey, hop_limit: u8) -> bool {
+		let Some(responder) = self.responders.get_mut(&key) else {
+			return false;
+		};
+		if responder.min_hop_limit.is_some_and(|min| min <= hop_limit) {
+			return false;
+		}
+		responder.min_hop_limit = Some(hop_limit);
+		true
+	}
+
+	/// Record the size of a probe the responder received in full.
+	pub fn record_size(&mut self, key: ResponderKey, size: usize) {
+		let Some(responder) = self.responders.get_mut(&key) else {
+			return;
+		};
+		if responder.max_size.is_none_or(|max| max < size) {
+			responder.max_size = Some(size);
+		}
+	}
+
+	/// Record when and on which interface the latest reply arrived.
+	pub fn record_arrival(&mut self, key: ResponderKey, time: u64, interface: Option<String>) {
+		let Some(responder) = self.responders.get_mut(&key) else {
+			return;
+		};
+		responder.last_seen = Some(time);
+		responder.interface = interface;
+	}
+
+	/// Record the one-way delays of an exchange with the responder.
+	pub fn record_exchange(&mut self, key: ResponderKey, exchange: Exchange) {
+		let Some(responder) = self.responders.get_mut(&key) else {
+			return;
+		};
+		responder.forward.add(exchange.forward);
+		responder.reverse.add(exchange.reverse);
+		if responder.best_exchange.is_none_or(|best| exchange.delay < best.delay) {
+			responder.best_exchange = Some(exchange);
+		}
+	}
+
+	/// Count a reply whose payload was corrupted or truncated.
+	pub fn record_verdict(&mut self, key: ResponderKey, verdict: Verdict) {
+		let Some(responder) = self.responders.get_mut(&key) else {
+			return;
+		};
+		match verdict {
+			Verdict::Corrupted => responder.corrupted += 1,
+			Verdict::Truncated => responder.truncated += 1,
+			Verdict::Intact | Verdict::Unchecked => {}
+		}
+	}
+
+	/// Record that probe `seq` couldn't be sent.
+	pub fn record_unsent(&mut self, seq: u64) {
+		self.unsent.insert(seq);
+	}
+
+	/// Mark responders that haven't answered any of the last `threshold`
+	/// probes up to `last_seq` as lost, returning the newly lost ones.
+	pub fn check_lost(&mut self, last_seq: u64, threshold: u64) -> Vec<(ResponderKey, ResponderEvent)> {
+		let mut events = Vec::new();
+		for (key, responder) in self.responders.iter_mut() {
+			if responder.last_seq >= last_seq {
+				continue;
+			}
+			let unsent = self.unsent.range(responder.last_seq + 1..=last_seq).count() as u64;
+			let missed = last_seq - responder.last_seq - unsent;
+			if !responder.lost && missed >= threshold {
+				responder.lost = true;
+				events.push((*key, ResponderEvent::Lost { missed }));
+			}
+		}
+		events
+	}
+
+	pub fn total_received(&self) -> u64 {
+		self.responders.values().map(|r| r.received).sum()
+	}
+
+	/// Highest jitter of any responder, in milliseconds.
+	pub fn max_jitter(&self) -> f64 {
+		self.responders.values().map(|r| r.jitter.value()).fold(0.0, f64::max)
+	}
+
+	pub fn total_corrupted(&self) -> u64 {
+		self.responders.values().map(|r| r.corrupted).sum()
+	}
+
+	pub fn total_truncated(&self) -> u64 {
+		self.responders.values().map(|r| r.truncated).sum()
+	}
+
+	/// Loss across all responders, weighting each by the probes it was sent.
+	pub fn loss_percent(&self, last_seq: u64) -> f64 {
+		let (sent, received) = self.responders.values().fold((0, 0), |(sent, received), r| {
+			let r_sent = r.sent(last_seq, &self.unsent);
+			(sent + r_sent, received + r.received.min(r_sent))
+		});
+		if sent == 0 {
+			return 100.0;
+		}
+		(sent - received) as f64 / sent as f64 * 100.0
+	}
+
+	pub fn to_json(&self, last_seq: u64) -> Vec<Object> {
+		self.responders.iter().map(|(key, responder)| responder.to_json(key, last_seq, &self.unsent)).collect()
+	}
+
+	/// Add per-responder Prometheus metrics to `w`.
+	pub fn write_metrics(&self, w: &mut metrics::Writer, last_seq: u64) {
+		let labels: Vec<(String, String)> = self
+			.responders
+			.keys()
+			.map(|key| (key.addr.to_string(), key.server_id.map(|id| format!("{:08x}", id)).unwrap_or_default()))
+			.collect();
+		let labels: Vec<[(&str, &str); 2]> =
+			labels.iter().map(|(addr, id)| [("responder", addr.as_str()), ("server_id", id.as_str())]).collect();
+		let responders = || self.responders.values().zip(&labels);
+
+		w.family("multicast_ping_responders", "gauge", "Number of responders seen.");
+		w.sample("multicast_ping_responders", &[], self.responders.len() as f64);
+
+		w.family("multicast_ping_replies_total", "counter", "Replies received, excluding duplicates.");
+		for (responder, labels) in responders() {
+			w.sample("multicast_ping_replies_total", labels, responder.received as f64);
+		}
+
+		w.family("multicast_ping_loss_ratio", "gauge", "Share of probes the responder didn't answer.");
+		for (responder, labels) in responders() {
+			w.sample("multicast_ping_loss_ratio", labels, responder.loss_percent(last_seq, &self.unsent) / 100.0);
+		}
+
+		w.family("multicast_ping_jitter_seconds", "gauge", "RFC 3550 interarrival jitter of the RTT.");
+		for (responder, labels) in responders() {
+			w.sample("multicast_ping_jitter_seconds", labels, responder.jitter.value() / 1000.0);
+		}
+
+		w.family("multicast_ping_rtt_seconds", "histogram", "Round-trip time of replies.");
+		for (responder, labels) in responders() {
+			let histogram = &responder.rtt_histogram;
+			for (bound, count) in histogram.buckets() {
+				let le = (bound / 1000.0).to_string();
+				let [responder_label, server_id] = *labels;
+				w.sample("multicast_ping_rtt_seconds_bucket", &[responder_label, server_id, ("le", &le)], count as f64);
+			}
+			let [responder_label, server_id] = *labels;
+			w.sample("multicast_ping_rtt_seconds_bucket", &[responder_label, server_id, ("le", "+Inf")],
+					 histogram.count() as f64);
+			w.sample("multicast_ping_rtt_seconds_sum", labels, histogram.sum() / 1000.0);
+			w.sample("multicast_ping_rtt_seconds_count", labels, histogram.count() as f64);
+		}
+	}
+
+	/// Print one line per responder, indented under the status line.
+	pub fn print(&self, last_seq: u64) {
+		if self.responders.is_empty() {
+			println!("    (no responders)");
+			return;
+		}
+		println!("    {:<48} {:>6} {:>6} {:>7} {:>6} {:>6} {:>6} {:>6} {:>6} {:>9}  RTT",
+				 "RESPONDER", "SENT", "RECV", "LOSS", "LATE", "DUP", "OOO", "CORR", "TRUNC", "JITTER");
+		for (key, responder) in &self.responders {
+			println!("    {:<48} {:>6} {:>6} {:>6.1}% {:>6} {:>6} {:>6} {:>6} {:>6} {:>7.3}ms  {}",
+					 key.to_string(), responder.sent(last_seq, &self.unsent), responder.received,
+					 responder.loss_percent(last_seq, &self.unsent), responder.late, responder.duplicates,
+					 responder.out_of_order, responder.corrupted, responder.truncated,
+					 responder.jitter.value(), responder.rtt);
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	fn key() -> ResponderKey {
+		ResponderKey { addr: "[ff02::1]:9999".parse().unwrap(), server_id: Some(1) }
+	}
+
+	#[test]
+	fn classifies_replies() {
+		let mut table = ResponderTable::new();
+		assert_eq!(table.record_reply(key(), 1, 1.0, false), (Some(ResponderEvent::New), ReplyClass::OnTime));
+		assert_eq!(table.record_reply(key(), 3, 1.0, false), (None, ReplyClass::OnTime));
+		assert_eq!(table.record_reply(key(), 2, 1.0, false), (None, ReplyClass::OutOfOrder));
+		assert_eq!(table.record_reply(key(), 3, 1.0, false), (None, ReplyClass::Duplicate));
+		assert_eq!(table.record_reply(key(), 4, 9.0, true), (None, ReplyClass::Late));
+
+		let responder = &table.responders[&key()];
+		assert_eq!(responder.received, 4);
+		assert_eq!((responder.late, responder.duplicates, responder.out_of_order), (1, 1, 1));
+		assert_eq!(responder.sent(5, &table.unsent), 5);
+	}
+
+	#[test]
+	fn lost_and_recovered() {
+		let mut table = ResponderTable::new();
+		table.record_reply(key(), 1, 1.0, false);
+		assert!(table.check_lost(3, 3).is_empty());
+		assert_eq!(table.check_lost(4, 3), vec![(key(), ResponderEvent::Lost { missed: 3 })]);
+		assert!(table.check_lost(5, 3).is_empty());
+		let (event, _) = table.record_reply(key(), 6, 1.0, false);
+		assert_eq!(event, Some(ResponderEvent::Recovered { missed: 4 }));
+	}
+
+	#[test]
+	fn unsent_probes_are_not_lost() {
+		let mut table = ResponderTable::new();
+		table.record_reply(key(), 1, 1.0, false);
+		for seq in 2..=4 {
+			table.record_unsent(seq);
+		}
+		assert!(table.check_lost(4, 3).is_empty());
+		assert_eq!(table.responders[&key()].sent(4, &table.unsent), 1);
+		assert_eq!(table.responders[&key()].loss_percent(5, &table.unsent), 50.0);
+		let (event, _) = table.record_reply(key(), 6, 1.0, false);
+		assert_eq!(event, None);
+	}
+	#[test]
+	fn stale_reply_is_not_a_recovery() {
+		let mut table = ResponderTable::new();
+		table.record_reply(key(), 1, 1.0, false);
+		table.record_reply(key(), 3, 1.0, false);
+		table.record_unsent(10);
+		assert_eq!(table.check_lost(4, 1), vec![(key(), ResponderEvent::Lost { missed: 1 })]);
+		assert_eq!(table.record_reply(key(), 2, 1.0, true), (None, ReplyClass::Late));
+		assert!(table.responders[&key()].lost);
+		let (event, _) = table.record_reply(key(), 5, 1.0, false);
+		assert_eq!(event, Some(ResponderEvent::Recovered { missed: 1 }));
+	}
+}
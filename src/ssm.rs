@@ -0,0 +1,154 @@
+//! Source-filtered multicast joins (MLDv2 / IGMPv3).
+//!
+//! On Linux this uses the protocol-independent API from RFC 3678
+//! (`MCAST_JOIN_SOURCE_GROUP`, `MCAST_BLOCK_SOURCE`), which works the same
+//! for IPv4 and IPv6. Elsewhere only IPv4 include-mode joins are supported.
+
+use clap::ValueEnum;
+use socket2::Socket;
+use std::io;
+use std::net::IpAddr;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum FilterMode {
+	/// Receive only from the listed sources
+	Include,
+	/// Receive from every source except the listed ones
+	Exclude,
+}
+
+/// Join `group` on `interface_index` with a source filter.
+pub fn join_filtered(
+	socket: &Socket,
+	group: IpAddr,
+	sources: &[IpAddr],
+	mode: FilterMode,
+	interface_index: u32,
+) -> io::Result<()> {
+	if let Some(source) = sources.iter().find(|s| s.is_ipv4() != group.is_ipv4()) {
+		return Err(io::Error::new(
+			io::ErrorKind::InvalidInput,
+			format!("Source {} is not in the same address family as group {}", source, group),
+		));
+	}
+
+	#[cfg(target_os = "linux")]
+	{
+		linux::join_filtered(socket, group, sources, mode, interface_index)
+	}
+
+	#[cfg(not(target_os = "linux"))]
+	{
+		match (group, mode) {
+			(IpAddr::V4(group), FilterMode::Include) if interface_index == 0 => {
+				for source in sources {
+					if let IpAddr::V4(source) = source {
+						socket.join_ssm_v4(source, &group, &std::net::Ipv4Addr::UNSPECIFIED)?;
+					}
+				}
+				Ok(())
+			}
+			_ => Err(io::Error::new(
+				io::ErrorKind::Unsupported,
+				"Only IPv4 include-mode source filters on the default interface are supported on this platform",
+			)),
+		}
+	}
+}
+
+#[cfg(target_os = "linux")]
+mod linux {
+	use super::FilterMode;
+	use socket2::{SockAddr, Socket};
+	use std::io;
+	use std::mem;
+	use std::net::{IpAddr, SocketAddr};
+	use std::os::fd::AsRawFd;
+
+	/// `struct group_req` from <netinet/in.h>
+	#[repr(C)]
+	struct GroupReq {
+		gr_interface: u32,
+		gr_group: libc::sockaddr_storage,
+	}
+
+	/// `struct group_source_req` from <netinet/in.h>
+	#[repr(C)]
+	struct GroupSourceReq {
+		gsr_interface: u32,
+		gsr_group: libc::sockaddr_storage,
+		gsr_source: libc::sockaddr_storage,
+	}
+
+	fn storage(addr: IpAddr) -> libc::sockaddr_storage {
+		let addr = SockAddr::from(SocketAddr::new(addr, 0));
+		// SAFETY: SockAddr wraps a sockaddr_storage of at least addr.len() bytes
+		let mut storage: libc::sockaddr_storage = unsafe { mem::zeroed() };
+		unsafe {
+			std::ptr::copy_nonoverlapping(
+				addr.as_ptr() as *const u8,
+				&mut storage as *mut _ as *mut u8,
+				addr.len() as usize,
+			);
+		}
+		storage
+	}
+
+	fn setsockopt<T>(socket: &Socket, level: libc::c_int, name: libc::c_int, value: &T) -> io::Result<()> {
+		let ret = unsafe {
+			libc::setsockopt(
+				socket.as_raw_fd(),
+				level,
+				name,
+				value as *const T as *const libc::c_void,
+				mem::size_of::<T>() as libc::socklen_t,
+			)
+		};
+		if ret != 0 {
+			return Err(io::Error::last_os_error());
+		}
+		Ok(())
+	}
+
+	pub fn join_filtered(
+		socket: &Socket,
+		group: IpAddr,
+		sources: &[IpAddr],
+		mode: FilterMode,
+		interface_index: u32,
+	) -> io::Result<()> {
+		let level = match group {
+			IpAddr::V4(_) => libc::IPPROTO_IP,
+			IpAddr::V6(_) => libc::IPPROTO_IPV6,
+		};
+
+		match mode {
+			FilterMode::Include => {
+				for source in sources {
+					let req = GroupSourceReq {
+						gsr_interface: interface_index,
+						gsr_group: storage(group),
+						gsr_source: storage(*source),
+					};
+					setsockopt(socket, level, libc::MCAST_JOIN_SOURCE_GROUP, &req)?;
+				}
+			}
+			FilterMode::Exclude => {
+				let req = GroupReq {
+					gr_interface: interface_index,
+					gr_group: storage(group),
+				};
+				setsockopt(socket, level, libc::MCAST_JOIN_GROUP, &req)?;
+				for source in sources {
+					let req = GroupSourceReq {
+						gsr_interface: interface_index,
+						gsr_group: storage(group),
+						gsr_source: storage(*source),
+					};
+					setsockopt(socket, level, libc::MCAST_BLOCK_SOURCE, &req)?;
+				}
+			}
+		}
+		Ok(())
+	}
+}
@@ -0,0 +1,235 @@
+//! Running round-trip time and jitter statistics.
+
+use crate::json::Object;
+use std::collections::VecDeque;
+use std::fmt;
+
+/// Number of most recent samples kept for percentile calculation.
+const PERCENTILE_WINDOW: usize = 10_000;
+
+/// ping-style RTT statistics. min/avg/max/mdev cover every sample seen,
+/// percentiles cover the last `PERCENTILE_WINDOW` samples.
+#[derive(Debug, Clone, Default)]
+pub struct RttStats {
+	count: u64,
+	sum: f64,
+	sum_sq: f64,
+	min: f64,
+	max: f64,
+	recent: VecDeque<f64>,
+}
+
+impl RttStats {
+	/// Record a sample, in milliseconds.
+	pub fn add(&mut self, rtt_ms: f64) {
+		if self.count == 0 || rtt_ms < self.min {
+			self.min = rtt_ms;
+		}
+		if self.count == 0 || rtt_ms > self.max {
+			self.max = rtt_ms;
+		}
+		self.count += 1;
+		self.sum += rtt_ms;
+		self.sum_sq += rtt_ms * rtt_ms;
+
+		if self.recent.len() == PERCENTILE_WINDOW {
+			self.recent.pop_front();
+		}
+		self.recent.push_back(rtt_ms);
+	}
+
+	pub fn count(&self) -> u64 {
+		self.count
+	}
+
+	pub fn min(&self) -> f64 {
+		self.min
+	}
+
+	pub fn max(&self) -> f64 {
+		self.max
+	}
+
+	pub fn avg(&self) -> f64 {
+		if self.count == 0 {
+			0.0
+		} else {
+			self.sum / self.count as f64
+		}
+	}
+
+	/// Mean deviation as reported by ping: sqrt(E[x²] - E[x]²).
+	pub fn mdev(&self) -> f64 {
+		if self.count == 0 {
+			return 0.0;
+		}
+		let avg = self.avg();
+		(self.sum_sq / self.count as f64 - avg * avg).max(0.0).sqrt()
+	}
+
+	/// The last `n` samples, oldest first.
+	pub fn recent(&self, n: usize) -> impl Iterator<Item = f64> + '_ {
+		self.recent.iter().copied().skip(self.recent.len().saturating_sub(n))
+	}
+
+	/// Nearest-rank percentile, `p` in 0..=100.
+	pub fn percentile(&self, p: f64) -> f64 {
+		if self.recent.is_empty() {
+			return 0.0;
+		}
+		let mut sorted: Vec<f64> = self.recent.iter().copied().collect();
+		sorted.sort_by(|a, b| a.total_cmp(b));
+		let rank = ((p / 100.0) * sorted.len() as f64).ceil() as usize;
+		sorted[rank.clamp(1, sorted.len()) - 1]
+	}
+
+	/// Add `{prefix}_min_ms`, `_avg_ms`, `_max_ms`, `_mdev_ms`, `_p50_ms`,
+	/// `_p90_ms` and `_p99_ms` fields to `object`, null without samples.
+	pub fn json_fields(&self, object: Object, prefix: &str) -> Object {
+		let value = |v: f64| (self.count > 0).then_some(v);
+		object
+			.field(&format!("{}_min_ms", prefix), value(self.min()))
+			.field(&format!("{}_avg_ms", prefix), value(self.avg()))
+			.field(&format!("{}_max_ms", prefix), value(self.max()))
+			.field(&format!("{}_mdev_ms", prefix), value(self.mdev()))
+			.field(&format!("{}_p50_ms", prefix), value(self.percentile(50.0)))
+			.field(&format!("{}_p90_ms", prefix), value(self.percentile(90.0)))
+			.field(&format!("{}_p99_ms", prefix), value(self.percentile(99.0)))
+	}
+}
+
+impl fmt::Display for RttStats {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		if self.count == 0 {
+			return write!(f, "rtt n/a");
+		}
+		write!(
+			f,
+			"rtt min/avg/max/mdev = {:.3}/{:.3}/{:.3}/{:.3} ms, p50/p90/p99 = {:.3}/{:.3}/{:.3} ms",
+			self.min(),
+			self.avg(),
+			self.max(),
+			self.mdev(),
+			self.percentile(50.0),
+			self.percentile(90.0),
+			self.percentile(99.0),
+		)
+	}
+}
+
+/// RFC 3550 interarrival jitter: a running estimate of the mean deviation of
+/// the difference in transit time between consecutive packets, smoothed with
+/// a gain of 1/16.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Jitter {
+	last_transit: Option<f64>,
+	jitter: f64,
+}
+
+impl Jitter {
+	/// Record the transit time of the next packet to arrive, in milliseconds.
+	pub fn add(&mut self, transit_ms: f64) {
+		if let Some(last) = self.last_transit {
+			let d = (transit_ms - last).abs();
+			self.jitter += (d - self.jitter) / 16.0;
+		}
+		self.last_transit = Some(transit_ms);
+	}
+
+	/// Current estimate in milliseconds, 0 until two packets have arrived.
+	pub fn value(&self) -> f64 {
+		self.jitter
+	}
+}
+
+/// Upper bounds of the RTT histogram buckets, in milliseconds.
+pub const HISTOGRAM_BUCKETS_MS: [f64; 15] =
+	[0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0, 25.0, 50.0, 100.0, 250.0, 500.0, 1000.0, 2500.0, 5000.0];
+
+/// Cumulative histogram of RTTs over `HISTOGRAM_BUCKETS_MS`, as Prometheus
+/// expects it.
+#[derive(Debug, Clone, Default)]
+pub struct Histogram {
+	/// Samples at or below each bucket's bound
+	buckets: [u64; HISTOGRAM_BUCKETS_MS.len()],
+	count: u64,
+	sum: f64,
+}
+
+impl Histogram {
+	pub fn add(&mut self, rtt_ms: f64) {
+		for (bucket, bound) in self.buckets.iter_mut().zip(HISTOGRAM_BUCKETS_MS) {
+			if rtt_ms <= bound {
+				*bucket += 1;
+			}
+		}
+		self.count += 1;
+		self.sum += rtt_ms;
+	}
+
+	/// `(upper bound in ms, cumulative count)` for each bucket.
+	pub fn buckets(&self) -> impl Iterator<Item = (f64, u64)> + '_ {
+		HISTOGRAM_BUCKETS_MS.iter().copied().zip(self.buckets.iter().copied())
+	}
+
+	pub fn count(&self) -> u64 {
+		self.count
+	}
+
+	pub fn sum(&self) -> f64 {
+		self.sum
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn percentiles() {
+		let mut stats = RttStats::default();
+		assert_eq!(stats.percentile(50.0), 0.0);
+		// Added out of order; percentiles rank the sorted samples
+		for rtt in (1..=100).rev() {
+			stats.add(rtt as f64);
+		}
+		assert_eq!(stats.percentile(0.0), 1.0);
+		assert_eq!(stats.percentile(50.0), 50.0);
+		assert_eq!(stats.percentile(90.0), 90.0);
+		assert_eq!(stats.percentile(99.0), 99.0);
+		assert_eq!(stats.percentile(99.5), 100.0);
+		assert_eq!(stats.percentile(100.0), 100.0);
+	}
+
+	#[test]
+	fn summary() {
+		let mut stats = RttStats::default();
+		for rtt in [2.0, 4.0, 4.0, 4.0, 5.0, 5.0, 7.0, 9.0] {
+			stats.add(rtt);
+		}
+		assert_eq!((stats.count(), stats.min(), stats.avg(), stats.max()), (8, 2.0, 5.0, 9.0));
+		assert_eq!(stats.mdev(), 2.0);
+		assert_eq!(stats.recent(2).collect::<Vec<_>>(), [7.0, 9.0]);
+	}
+
+	#[test]
+	fn jitter_gain() {
+		let mut jitter = Jitter::default();
+		jitter.add(10.0);
+		assert_eq!(jitter.value(), 0.0);
+		jitter.add(26.0);
+		assert_eq!(jitter.value(), 1.0);
+	}
+
+	#[test]
+	fn histogram_is_cumulative() {
+		let mut histogram = Histogram::default();
+		histogram.add(0.3);
+		histogram.add(1.0);
+		histogram.add(10_000.0);
+		let buckets: Vec<u64> = histogram.buckets().map(|(_, count)| count).collect();
+		assert_eq!(&buckets[..4], [0, 0, 1, 2]);
+		assert_eq!(buckets[HISTOGRAM_BUCKETS_MS.len() - 1], 2);
+		assert_eq!(histogram.count(), 3);
+	}
+}
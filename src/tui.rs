@@ -0,0 +1,88 @@
+//! Full-screen client dashboard (`--tui`).
+//!
+//! Redraws a table of responders in place using ANSI escape sequences on the
+//! terminal's alternate screen, instead of the scrolling per-probe output.
+
+use crate::json;
+use crate::responders::ResponderTable;
+use std::fmt::Write as _;
+use std::io::{self, Write};
+use std::time::Duration;
+
+/// Number of recent RTTs shown in each responder's sparkline.
+const SPARKLINE_LEN: usize = 20;
+const SPARKS: [char; 8] = ['▁', '▂', '▃', '▄', '▅', '▆', '▇', '█'];
+/// How often the dashboard is redrawn between probes.
+pub const REFRESH: Duration = Duration::from_millis(250);
+
+/// The dashboard's hold on the terminal: the alternate screen with the
+/// cursor hidden, restored when dropped, including on early returns.
+pub struct Screen(());
+
+impl Screen {
+	pub fn enter() -> Self {
+		print!("\x1b[?1049h\x1b[?25l");
+		let _ = io::stdout().flush();
+		json::capture_reports(true);
+		Screen(())
+	}
+}
+
+impl Drop for Screen {
+	fn drop(&mut self) {
+		json::capture_reports(false);
+		print!("\x1b[?25h\x1b[?1049l");
+		let _ = io::stdout().flush();
+	}
+}
+
+/// Redraw the dashboard. `header` is the first line, `now` the current
+/// `Clock` time and `last_seq` the latest probe that has had time to be
+/// answered. `status` overrides the latest reported message.
+pub fn draw(header: &str, table: &ResponderTable, now: u64, last_seq: u64, status: Option<&str>) {
+	let reported = json::last_report();
+	let status = status.or(reported.as_deref());
+	let mut out = String::from("\x1b[H");
+	let _ = writeln!(out, "{}\x1b[K", header);
+	let _ = writeln!(out, "\x1b[K");
+	let _ = writeln!(out, "\x1b[1m  {:<48} {:<10} {:>10} {:>10} {:>7} {:>10} {:>10}  RECENT RTT\x1b[0m\x1b[K",
+					 "RESPONDER", "INTERFACE", "LAST RTT", "AVG", "LOSS", "JITTER", "LAST SEEN");
+	if table.responders.is_empty() {
+		let _ = writeln!(out, "  (no responders yet)\x1b[K");
+	}
+	for (key, responder) in &table.responders {
+		let marker = if responder.lost { "✗" } else { " " };
+		let last_rtt = responder.last_rtt.map(|rtt| format!("{:.3} ms", rtt)).unwrap_or_default();
+		let last_seen = responder
+			.last_seen
+			.map(|seen| format!("{:.1}s ago", now.saturating_sub(seen) as f64 / 1e9))
+			.unwrap_or_default();
+		let _ = writeln!(out, "{} {:<48} {:<10} {:>10} {:>7.3} ms {:>6.1}% {:>7.3} ms {:>10}  {}\x1b[K",
+						 marker, key.to_string(), responder.interface.as_deref().unwrap_or("?"), last_rtt,
+						 responder.rtt.avg(), responder.loss_percent(last_seq, &table.unsent), responder.jitter.value(),
+						 last_seen, sparkline(&responder.rtt.recent(SPARKLINE_LEN).collect::<Vec<_>>()));
+	}
+	if let Some(status) = status {
+		let _ = writeln!(out, "\x1b[K\n{}\x1b[K", status);
+	}
+	out.push_str("\x1b[J");
+	print!("{}", out);
+	let _ = io::stdout().flush();
+}
+
+/// One block character per sample, scaled between the smallest and largest.
+fn sparkline(samples: &[f64]) -> String {
+	let min = samples.iter().copied().fold(f64::INFINITY, f64::min);
+	let max = samples.iter().copied().fold(f64::NEG_INFINITY, f64::max);
+	let range = max - min;
+	samples
+		.iter()
+		.map(|&sample| {
+			if range <= 0.0 {
+				return SPARKS[0];
+			}
+			let level = ((sample - min) / range * (SPARKS.len() - 1) as f64).round() as usize;
+			SPARKS[level.min(SPARKS.len() - 1)]
+		})
+		.collect()
+}